@@ -330,7 +330,7 @@ impl Database {
 }
 
 
-// CREATE TABLE videos (id INTEGER PRIMARY KEY, room_id INTEGER, cover TEXT, file TEXT, length INTEGER, size INTEGER, status INTEGER, bvid TEXT, title TEXT, desc TEXT, tags TEXT, area INTEGER, created_at TEXT);
+// CREATE TABLE videos (id INTEGER PRIMARY KEY, room_id INTEGER, cover TEXT, file TEXT, length INTEGER, size INTEGER, status INTEGER, bvid TEXT, title TEXT, desc TEXT, tags TEXT, area INTEGER, created_at TEXT, format TEXT, loudness TEXT, review_state INTEGER, review_desc TEXT, reject_reason TEXT, live_id INTEGER);
 #[derive(Debug, Clone, serde::Serialize, sqlx::FromRow)]
 pub struct VideoRow {
     pub id: i64,
@@ -346,6 +346,16 @@ pub struct VideoRow {
     pub tags: String,
     pub area: i64,
     pub created_at: String,
+    /// mp4, audio, gif or webp
+    pub format: String,
+    /// JSON of measured loudness if clip is normalized
+    pub loudness: Option<String>,
+    /// state of submitted archive, None before it is checked
+    pub review_state: Option<i64>,
+    pub review_desc: Option<String>,
+    pub reject_reason: Option<String>,
+    /// archive the clip starts in, None for videos added before it is recorded
+    pub live_id: Option<u64>,
 }
 
 impl Database {
@@ -380,6 +390,36 @@ impl Database {
         Ok(())
     }
 
+    pub async fn update_video_cover(&self, video_id: i64, cover: &str) -> Result<(), DatabaseError> {
+        let lock = self.db.read().await.clone().unwrap();
+        sqlx::query("UPDATE videos SET cover = $1 WHERE id = $2")
+            .bind(cover)
+            .bind(video_id)
+            .execute(&lock)
+            .await?;
+        Ok(())
+    }
+
+    /// Submitted videos, several videos share one bvid if they are parts of one submission
+    pub async fn get_submitted_videos(&self) -> Result<Vec<VideoRow>, DatabaseError> {
+        let lock = self.db.read().await.clone().unwrap();
+        Ok(sqlx::query_as::<_, VideoRow>("SELECT * FROM videos WHERE status = 1 AND bvid != ''")
+            .fetch_all(&lock)
+            .await?)
+    }
+
+    pub async fn update_video_review(&self, bvid: &str, state: i64, desc: &str, reason: &str) -> Result<(), DatabaseError> {
+        let lock = self.db.read().await.clone().unwrap();
+        sqlx::query("UPDATE videos SET review_state = $1, review_desc = $2, reject_reason = $3 WHERE bvid = $4")
+            .bind(state)
+            .bind(desc)
+            .bind(reason)
+            .bind(bvid)
+            .execute(&lock)
+            .await?;
+        Ok(())
+    }
+
     pub async fn delete_video(&self, id: i64) -> Result<(), DatabaseError> {
         let lock = self.db.read().await.clone().unwrap();
         sqlx::query("DELETE FROM videos WHERE id = $1")
@@ -402,6 +442,9 @@ impl Database {
         desc: &str,
         tags: &str,
         area: i64,
+        format: &str,
+        loudness: Option<String>,
+        live_id: Option<u64>,
     ) -> Result<VideoRow, DatabaseError> {
         let lock = self.db.read().await.clone().unwrap();
         let mut video = VideoRow {
@@ -418,8 +461,14 @@ impl Database {
             tags: tags.into(),
             area,
             created_at: Utc::now().to_rfc3339(),
+            format: format.into(),
+            loudness,
+            review_state: None,
+            review_desc: None,
+            reject_reason: None,
+            live_id,
         };
-        let sql = sqlx::query("INSERT INTO videos (room_id, cover, file, length, size, status, bvid, title, desc, tags, area, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)")
+        let sql = sqlx::query("INSERT INTO videos (room_id, cover, file, length, size, status, bvid, title, desc, tags, area, created_at, format, loudness, live_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)")
             .bind(video.room_id as i64)
             .bind(&video.cover)
             .bind(&video.file)
@@ -432,9 +481,474 @@ impl Database {
             .bind(&video.tags)
             .bind(video.area)
             .bind(&video.created_at)
+            .bind(&video.format)
+            .bind(&video.loudness)
+            .bind(video.live_id.map(|id| id as i64))
             .execute(&lock)
             .await?;
         video.id = sql.last_insert_rowid();
         Ok(video)
     }
 }
+
+// CREATE TABLE bookmarks (id INTEGER PRIMARY KEY AUTOINCREMENT, room_id INTEGER, live_id INTEGER, offset REAL, note TEXT, created_at TEXT);
+#[derive(Debug, Clone, serde::Serialize, sqlx::FromRow)]
+pub struct BookmarkRow {
+    pub id: i64,
+    pub room_id: u64,
+    pub live_id: u64,
+    /// seconds relative to first sequence of the live, same as clip_range
+    pub offset: f64,
+    pub note: String,
+    pub created_at: String,
+}
+
+impl Database {
+    pub async fn add_bookmark(
+        &self,
+        room_id: u64,
+        live_id: u64,
+        offset: f64,
+        note: &str,
+    ) -> Result<BookmarkRow, DatabaseError> {
+        let lock = self.db.read().await.clone().unwrap();
+        let mut bookmark = BookmarkRow {
+            id: 0,
+            room_id,
+            live_id,
+            offset,
+            note: note.into(),
+            created_at: Utc::now().to_rfc3339(),
+        };
+        let sql = sqlx::query("INSERT INTO bookmarks (room_id, live_id, offset, note, created_at) VALUES ($1, $2, $3, $4, $5)")
+            .bind(bookmark.room_id as i64)
+            .bind(bookmark.live_id as i64)
+            .bind(bookmark.offset)
+            .bind(&bookmark.note)
+            .bind(&bookmark.created_at)
+            .execute(&lock)
+            .await?;
+        bookmark.id = sql.last_insert_rowid();
+        Ok(bookmark)
+    }
+
+    pub async fn get_bookmarks(
+        &self,
+        room_id: u64,
+        live_id: u64,
+    ) -> Result<Vec<BookmarkRow>, DatabaseError> {
+        let lock = self.db.read().await.clone().unwrap();
+        Ok(sqlx::query_as::<_, BookmarkRow>(
+            "SELECT * FROM bookmarks WHERE room_id = $1 and live_id = $2 ORDER BY offset",
+        )
+        .bind(room_id as i64)
+        .bind(live_id as i64)
+        .fetch_all(&lock)
+        .await?)
+    }
+
+    pub async fn get_bookmark(&self, id: i64) -> Result<BookmarkRow, DatabaseError> {
+        let lock = self.db.read().await.clone().unwrap();
+        Ok(
+            sqlx::query_as::<_, BookmarkRow>("SELECT * FROM bookmarks WHERE id = $1")
+                .bind(id)
+                .fetch_one(&lock)
+                .await?,
+        )
+    }
+
+    pub async fn delete_bookmark(&self, id: i64) -> Result<(), DatabaseError> {
+        let lock = self.db.read().await.clone().unwrap();
+        let sql = sqlx::query("DELETE FROM bookmarks WHERE id = $1")
+            .bind(id)
+            .execute(&lock)
+            .await?;
+        if sql.rows_affected() != 1 {
+            return Err(DatabaseError::NotFoundError);
+        }
+        Ok(())
+    }
+
+    pub async fn remove_bookmarks(&self, live_id: u64) -> Result<(), DatabaseError> {
+        let lock = self.db.read().await.clone().unwrap();
+        sqlx::query("DELETE FROM bookmarks WHERE live_id = $1")
+            .bind(live_id as i64)
+            .execute(&lock)
+            .await?;
+        Ok(())
+    }
+}
+
+// CREATE TABLE layouts (id INTEGER PRIMARY KEY AUTOINCREMENT, room_id INTEGER, name TEXT, width INTEGER, height INTEGER, blur_background INTEGER, regions TEXT, created_at TEXT);
+#[derive(Debug, Clone, serde::Serialize, sqlx::FromRow)]
+pub struct LayoutRow {
+    pub id: i64,
+    pub room_id: u64,
+    pub name: String,
+    pub width: i64,
+    pub height: i64,
+    pub blur_background: bool,
+    /// json array of crop regions
+    pub regions: String,
+    pub created_at: String,
+}
+
+impl Database {
+    pub async fn add_layout(
+        &self,
+        room_id: u64,
+        name: &str,
+        width: i64,
+        height: i64,
+        blur_background: bool,
+        regions: &str,
+    ) -> Result<LayoutRow, DatabaseError> {
+        let lock = self.db.read().await.clone().unwrap();
+        let mut layout = LayoutRow {
+            id: 0,
+            room_id,
+            name: name.into(),
+            width,
+            height,
+            blur_background,
+            regions: regions.into(),
+            created_at: Utc::now().to_rfc3339(),
+        };
+        let sql = sqlx::query("INSERT INTO layouts (room_id, name, width, height, blur_background, regions, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7)")
+            .bind(layout.room_id as i64)
+            .bind(&layout.name)
+            .bind(layout.width)
+            .bind(layout.height)
+            .bind(layout.blur_background)
+            .bind(&layout.regions)
+            .bind(&layout.created_at)
+            .execute(&lock)
+            .await?;
+        layout.id = sql.last_insert_rowid();
+        Ok(layout)
+    }
+
+    pub async fn get_layouts(&self, room_id: u64) -> Result<Vec<LayoutRow>, DatabaseError> {
+        let lock = self.db.read().await.clone().unwrap();
+        Ok(
+            sqlx::query_as::<_, LayoutRow>("SELECT * FROM layouts WHERE room_id = $1")
+                .bind(room_id as i64)
+                .fetch_all(&lock)
+                .await?,
+        )
+    }
+
+    /// Layout of room, layouts of other rooms are not found
+    pub async fn get_layout(&self, room_id: u64, id: i64) -> Result<LayoutRow, DatabaseError> {
+        let lock = self.db.read().await.clone().unwrap();
+        Ok(sqlx::query_as::<_, LayoutRow>(
+            "SELECT * FROM layouts WHERE id = $1 AND room_id = $2",
+        )
+        .bind(id)
+        .bind(room_id as i64)
+        .fetch_one(&lock)
+        .await?)
+    }
+
+    pub async fn delete_layout(&self, id: i64) -> Result<(), DatabaseError> {
+        let lock = self.db.read().await.clone().unwrap();
+        let sql = sqlx::query("DELETE FROM layouts WHERE id = $1")
+            .bind(id)
+            .execute(&lock)
+            .await?;
+        if sql.rows_affected() != 1 {
+            return Err(DatabaseError::NotFoundError);
+        }
+        Ok(())
+    }
+}
+
+// CREATE TABLE uploads (video_id INTEGER PRIMARY KEY, state TEXT, updated_at TEXT);
+#[derive(Debug, Clone, serde::Serialize, sqlx::FromRow)]
+pub struct UploadRow {
+    pub video_id: i64,
+    /// json of chunked upload state
+    pub state: String,
+    pub updated_at: String,
+}
+
+impl Database {
+    pub async fn save_upload(&self, video_id: i64, state: &str) -> Result<(), DatabaseError> {
+        let lock = self.db.read().await.clone().unwrap();
+        sqlx::query("INSERT OR REPLACE INTO uploads (video_id, state, updated_at) VALUES ($1, $2, $3)")
+            .bind(video_id)
+            .bind(state)
+            .bind(Utc::now().to_rfc3339())
+            .execute(&lock)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_upload(&self, video_id: i64) -> Result<UploadRow, DatabaseError> {
+        let lock = self.db.read().await.clone().unwrap();
+        Ok(
+            sqlx::query_as::<_, UploadRow>("SELECT * FROM uploads WHERE video_id = $1")
+                .bind(video_id)
+                .fetch_one(&lock)
+                .await?,
+        )
+    }
+
+    pub async fn remove_upload(&self, video_id: i64) -> Result<(), DatabaseError> {
+        let lock = self.db.read().await.clone().unwrap();
+        sqlx::query("DELETE FROM uploads WHERE video_id = $1")
+            .bind(video_id)
+            .execute(&lock)
+            .await?;
+        Ok(())
+    }
+}
+
+// CREATE TABLE upload_tasks (id INTEGER PRIMARY KEY AUTOINCREMENT, video_id INTEGER, uid INTEGER, room_id INTEGER, cover TEXT, profile TEXT, status TEXT, attempts INTEGER, error TEXT, bvid TEXT, next_retry_at INTEGER, created_at TEXT, updated_at TEXT, parts TEXT, cover43 TEXT);
+#[derive(Debug, Clone, serde::Serialize, sqlx::FromRow)]
+pub struct UploadTaskRow {
+    pub id: i64,
+    pub video_id: i64,
+    pub uid: u64,
+    pub room_id: u64,
+    /// data url of cover
+    pub cover: String,
+    /// json of submission profile
+    pub profile: String,
+    pub status: String,
+    pub attempts: i64,
+    /// error of the last attempt
+    pub error: String,
+    pub bvid: String,
+    /// milliseconds, queued task is not started before it
+    pub next_retry_at: i64,
+    pub created_at: String,
+    pub updated_at: String,
+    /// json array of videos with part titles, in order
+    pub parts: String,
+    /// data url or http url of 4:3 cover, only used on submission so it is not sent to frontend
+    #[serde(skip_serializing)]
+    pub cover43: Option<String>,
+}
+
+impl Database {
+    #[allow(clippy::too_many_arguments)]
+    pub async fn add_upload_task(
+        &self,
+        video_id: i64,
+        uid: u64,
+        room_id: u64,
+        cover: &str,
+        cover43: &str,
+        profile: &str,
+        parts: &str,
+        status: &str,
+        next_retry_at: i64,
+    ) -> Result<UploadTaskRow, DatabaseError> {
+        let lock = self.db.read().await.clone().unwrap();
+        let now = Utc::now().to_rfc3339();
+        let mut task = UploadTaskRow {
+            id: 0,
+            video_id,
+            uid,
+            room_id,
+            cover: cover.into(),
+            profile: profile.into(),
+            status: status.into(),
+            attempts: 0,
+            error: "".into(),
+            bvid: "".into(),
+            next_retry_at,
+            created_at: now.clone(),
+            updated_at: now,
+            parts: parts.into(),
+            cover43: Some(cover43.into()),
+        };
+        let sql = sqlx::query("INSERT INTO upload_tasks (video_id, uid, room_id, cover, profile, status, attempts, error, bvid, next_retry_at, created_at, updated_at, parts, cover43) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)")
+            .bind(task.video_id)
+            .bind(task.uid as i64)
+            .bind(task.room_id as i64)
+            .bind(&task.cover)
+            .bind(&task.profile)
+            .bind(&task.status)
+            .bind(task.attempts)
+            .bind(&task.error)
+            .bind(&task.bvid)
+            .bind(task.next_retry_at)
+            .bind(&task.created_at)
+            .bind(&task.updated_at)
+            .bind(&task.parts)
+            .bind(&task.cover43)
+            .execute(&lock)
+            .await?;
+        task.id = sql.last_insert_rowid();
+        Ok(task)
+    }
+
+    pub async fn get_upload_task(&self, id: i64) -> Result<UploadTaskRow, DatabaseError> {
+        let lock = self.db.read().await.clone().unwrap();
+        Ok(
+            sqlx::query_as::<_, UploadTaskRow>("SELECT * FROM upload_tasks WHERE id = $1")
+                .bind(id)
+                .fetch_one(&lock)
+                .await?,
+        )
+    }
+
+    pub async fn get_upload_tasks(&self) -> Result<Vec<UploadTaskRow>, DatabaseError> {
+        let lock = self.db.read().await.clone().unwrap();
+        Ok(
+            sqlx::query_as::<_, UploadTaskRow>("SELECT * FROM upload_tasks ORDER BY id DESC")
+                .fetch_all(&lock)
+                .await?,
+        )
+    }
+
+    /// Tasks of status that are due at now, in queue order
+    pub async fn get_due_upload_tasks(
+        &self,
+        status: &str,
+        now: i64,
+    ) -> Result<Vec<UploadTaskRow>, DatabaseError> {
+        let lock = self.db.read().await.clone().unwrap();
+        Ok(sqlx::query_as::<_, UploadTaskRow>(
+            "SELECT * FROM upload_tasks WHERE status = $1 AND next_retry_at <= $2 ORDER BY id",
+        )
+        .bind(status)
+        .bind(now)
+        .fetch_all(&lock)
+        .await?)
+    }
+
+    pub async fn update_upload_task(&self, task: &UploadTaskRow) -> Result<(), DatabaseError> {
+        let lock = self.db.read().await.clone().unwrap();
+        sqlx::query("UPDATE upload_tasks SET status = $1, attempts = $2, error = $3, bvid = $4, next_retry_at = $5, updated_at = $6 WHERE id = $7")
+            .bind(&task.status)
+            .bind(task.attempts)
+            .bind(&task.error)
+            .bind(&task.bvid)
+            .bind(task.next_retry_at)
+            .bind(Utc::now().to_rfc3339())
+            .bind(task.id)
+            .execute(&lock)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_upload_task(&self, id: i64) -> Result<(), DatabaseError> {
+        let lock = self.db.read().await.clone().unwrap();
+        let sql = sqlx::query("DELETE FROM upload_tasks WHERE id = $1")
+            .bind(id)
+            .execute(&lock)
+            .await?;
+        if sql.rows_affected() != 1 {
+            return Err(DatabaseError::NotFoundError);
+        }
+        Ok(())
+    }
+
+    pub async fn remove_upload_tasks(&self, video_id: i64) -> Result<(), DatabaseError> {
+        let lock = self.db.read().await.clone().unwrap();
+        sqlx::query("DELETE FROM upload_tasks WHERE video_id = $1")
+            .bind(video_id)
+            .execute(&lock)
+            .await?;
+        Ok(())
+    }
+}
+
+// CREATE TABLE profile_templates (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT, title TEXT, tid INTEGER, tag TEXT, desc TEXT, copyright INTEGER, dynamic TEXT, created_at TEXT);
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize, sqlx::FromRow)]
+pub struct ProfileTemplateRow {
+    /// 0 for new template
+    #[serde(default)]
+    pub id: i64,
+    pub name: String,
+    /// title, desc and dynamic are templates with placeholders like `{streamer}`
+    pub title: String,
+    pub tid: u64,
+    pub tag: String,
+    pub desc: String,
+    /// 1 自制，2 转载
+    pub copyright: u8,
+    pub dynamic: String,
+    #[serde(default)]
+    pub created_at: String,
+}
+
+impl Database {
+    pub async fn add_profile_template(
+        &self,
+        template: &ProfileTemplateRow,
+    ) -> Result<ProfileTemplateRow, DatabaseError> {
+        let lock = self.db.read().await.clone().unwrap();
+        let mut template = template.clone();
+        template.created_at = Utc::now().to_rfc3339();
+        let sql = sqlx::query("INSERT INTO profile_templates (name, title, tid, tag, desc, copyright, dynamic, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)")
+            .bind(&template.name)
+            .bind(&template.title)
+            .bind(template.tid as i64)
+            .bind(&template.tag)
+            .bind(&template.desc)
+            .bind(template.copyright)
+            .bind(&template.dynamic)
+            .bind(&template.created_at)
+            .execute(&lock)
+            .await?;
+        template.id = sql.last_insert_rowid();
+        Ok(template)
+    }
+
+    pub async fn update_profile_template(
+        &self,
+        template: &ProfileTemplateRow,
+    ) -> Result<(), DatabaseError> {
+        let lock = self.db.read().await.clone().unwrap();
+        let sql = sqlx::query("UPDATE profile_templates SET name = $1, title = $2, tid = $3, tag = $4, desc = $5, copyright = $6, dynamic = $7 WHERE id = $8")
+            .bind(&template.name)
+            .bind(&template.title)
+            .bind(template.tid as i64)
+            .bind(&template.tag)
+            .bind(&template.desc)
+            .bind(template.copyright)
+            .bind(&template.dynamic)
+            .bind(template.id)
+            .execute(&lock)
+            .await?;
+        if sql.rows_affected() != 1 {
+            return Err(DatabaseError::NotFoundError);
+        }
+        Ok(())
+    }
+
+    pub async fn get_profile_templates(&self) -> Result<Vec<ProfileTemplateRow>, DatabaseError> {
+        let lock = self.db.read().await.clone().unwrap();
+        Ok(
+            sqlx::query_as::<_, ProfileTemplateRow>("SELECT * FROM profile_templates")
+                .fetch_all(&lock)
+                .await?,
+        )
+    }
+
+    pub async fn get_profile_template(&self, id: i64) -> Result<ProfileTemplateRow, DatabaseError> {
+        let lock = self.db.read().await.clone().unwrap();
+        Ok(
+            sqlx::query_as::<_, ProfileTemplateRow>("SELECT * FROM profile_templates WHERE id = $1")
+                .bind(id)
+                .fetch_one(&lock)
+                .await?,
+        )
+    }
+
+    pub async fn delete_profile_template(&self, id: i64) -> Result<(), DatabaseError> {
+        let lock = self.db.read().await.clone().unwrap();
+        let sql = sqlx::query("DELETE FROM profile_templates WHERE id = $1")
+            .bind(id)
+            .execute(&lock)
+            .await?;
+        if sql.rows_affected() != 1 {
+            return Err(DatabaseError::NotFoundError);
+        }
+        Ok(())
+    }
+}
@@ -5,14 +5,29 @@ mod db;
 mod recorder;
 mod recorder_manager;
 mod tray;
+mod upload_manager;
 
 use custom_error::custom_error;
-use db::{AccountRow, Database, MessageRow, RecordRow, VideoRow};
+use db::{
+    AccountRow, BookmarkRow, Database, LayoutRow, MessageRow, ProfileTemplateRow, RecordRow,
+    UploadTaskRow, VideoRow,
+};
 use recorder::bilibili::errors::BiliClientError;
 use recorder::bilibili::profile::Profile;
+use recorder::bilibili::upload::{UploadOptions, UploadProgress};
 use recorder::bilibili::{BiliClient, QrInfo, QrStatus};
+use recorder::cover;
+use recorder::encoding::EncodingPreset;
+use recorder::format::ClipFormat;
+use recorder::layout::{CropRegion, VerticalLayout};
+use recorder::naming;
+use recorder::{
+    ClipOptions, ClipRange, ClipResult, CompilationOptions, MergedSession, SessionEnd,
+};
 use recorder_manager::{RecorderInfo, RecorderList, RecorderManager};
+use upload_manager::{AutoUploadRule, UploadManager, UploadPart};
 use tauri_plugin_notification::NotificationExt;
+use std::collections::HashMap;
 use std::path::Path;
 use std::process::Command;
 use std::sync::Arc;
@@ -87,6 +102,57 @@ pub struct Config {
     live_end_notify: bool,
     clip_notify: bool,
     post_notify: bool,
+    #[serde(default = "EncodingPreset::builtin")]
+    encoding_presets: Vec<EncodingPreset>,
+    /// room_id -> name of default encoding preset
+    #[serde(default)]
+    room_presets: HashMap<String, String>,
+    /// room_id -> id of default upload profile template
+    #[serde(default)]
+    room_profile_templates: HashMap<String, i64>,
+    /// room_id -> rule of uploading archive when live ends
+    #[serde(default)]
+    auto_upload_rules: HashMap<String, AutoUploadRule>,
+    /// file name template for clips of the current live
+    #[serde(default = "naming::default_clip_template")]
+    clip_name_template: String,
+    /// file name template for clips of archives
+    #[serde(default = "naming::default_archive_template")]
+    archive_name_template: String,
+    /// write clip metadata into `{clip}.json` next to clip
+    #[serde(default)]
+    clip_sidecar: bool,
+    /// font file of compilation title cards, a platform font is used if empty
+    #[serde(default)]
+    title_font: String,
+    /// chunks of a video uploaded at the same time
+    #[serde(default = "default_upload_workers")]
+    upload_workers: usize,
+    /// retries of every chunk before upload fails
+    #[serde(default = "default_upload_retries")]
+    upload_retries: u32,
+    /// videos uploaded at the same time by upload queue
+    #[serde(default = "default_upload_concurrency")]
+    upload_concurrency: usize,
+    /// attempts of an upload task before it is marked failed
+    #[serde(default = "default_upload_max_attempts")]
+    upload_max_attempts: u32,
+}
+
+fn default_upload_workers() -> usize {
+    UploadOptions::default().workers
+}
+
+fn default_upload_retries() -> u32 {
+    UploadOptions::default().retries
+}
+
+fn default_upload_concurrency() -> usize {
+    1
+}
+
+fn default_upload_max_attempts() -> u32 {
+    3
 }
 
 impl Config {
@@ -118,6 +184,18 @@ impl Config {
             live_end_notify: true,
             clip_notify: true,
             post_notify: true,
+            encoding_presets: EncodingPreset::builtin(),
+            room_presets: HashMap::new(),
+            room_profile_templates: HashMap::new(),
+            auto_upload_rules: HashMap::new(),
+            clip_name_template: naming::default_clip_template(),
+            archive_name_template: naming::default_archive_template(),
+            clip_sidecar: false,
+            title_font: "".to_string(),
+            upload_workers: default_upload_workers(),
+            upload_retries: default_upload_retries(),
+            upload_concurrency: default_upload_concurrency(),
+            upload_max_attempts: default_upload_max_attempts(),
         };
         config.save();
         config
@@ -148,6 +226,84 @@ impl Config {
         self.save();
     }
 
+    /// Add preset or replace the one with the same name
+    pub fn set_encoding_preset(&mut self, preset: EncodingPreset) -> Result<(), String> {
+        preset.validate()?;
+        if let Some(p) = self
+            .encoding_presets
+            .iter_mut()
+            .find(|p| p.name == preset.name)
+        {
+            *p = preset;
+        } else {
+            self.encoding_presets.push(preset);
+        }
+        self.save();
+        Ok(())
+    }
+
+    pub fn remove_encoding_preset(&mut self, name: &str) {
+        self.encoding_presets.retain(|p| p.name != name);
+        // rooms using it fall back to default preset
+        self.room_presets.retain(|_, p| p != name);
+        self.save();
+    }
+
+    pub fn set_room_preset(&mut self, room_id: u64, name: Option<String>) -> Result<(), String> {
+        match name {
+            Some(name) => {
+                if !self.encoding_presets.iter().any(|p| p.name == name) {
+                    return Err(format!("Encoding preset {} not found", name));
+                }
+                self.room_presets.insert(room_id.to_string(), name);
+            }
+            None => {
+                self.room_presets.remove(&room_id.to_string());
+            }
+        }
+        self.save();
+        Ok(())
+    }
+
+    pub fn set_room_profile_template(&mut self, room_id: u64, id: Option<i64>) {
+        match id {
+            Some(id) => {
+                self.room_profile_templates.insert(room_id.to_string(), id);
+            }
+            None => {
+                self.room_profile_templates.remove(&room_id.to_string());
+            }
+        }
+        self.save();
+    }
+
+    pub fn set_auto_upload_rule(
+        &mut self,
+        room_id: u64,
+        rule: Option<AutoUploadRule>,
+    ) -> Result<(), String> {
+        match rule {
+            Some(rule) => {
+                rule.validate()?;
+                self.auto_upload_rules.insert(room_id.to_string(), rule);
+            }
+            None => {
+                self.auto_upload_rules.remove(&room_id.to_string());
+            }
+        }
+        self.save();
+        Ok(())
+    }
+
+    pub fn set_name_templates(&mut self, clip: &str, archive: &str) -> Result<(), String> {
+        naming::validate(clip)?;
+        naming::validate(archive)?;
+        self.clip_name_template = clip.into();
+        self.archive_name_template = archive.into();
+        self.save();
+        Ok(())
+    }
+
     pub fn webid_expired(&self) -> bool {
         let now = chrono::Utc::now().timestamp();
         // expire in 20 hours
@@ -179,6 +335,7 @@ struct State {
     config: Arc<RwLock<Config>>,
     recorder_manager: Arc<RecorderManager>,
     app_handle: tauri::AppHandle,
+    upload_manager: Arc<UploadManager>,
 }
 
 impl State {
@@ -194,7 +351,190 @@ impl State {
         Ok(self
             .recorder_manager
             .clip(&self.config.read().await.output, room_id, len)
-            .await?)
+            .await?
+            .file)
+    }
+
+    pub async fn clip_range(
+        &self,
+        cover: &str,
+        room_id: u64,
+        ts: u64,
+        x: f64,
+        y: f64,
+        options: &ClipOptions,
+    ) -> Result<VideoRow, String> {
+        log::info!(
+            "Clip room_id: {}, ts: {}, start: {}, end: {}",
+            room_id,
+            ts,
+            x,
+            y
+        );
+        let clip = self
+            .recorder_manager
+            .clip_range(&self.config.read().await.output, room_id, ts, x, y, options)
+            .await?;
+        self.save_clip(cover, room_id, &clip, y - x).await
+    }
+
+    /// Clip archive of ended session by auto upload rule of room and add it into upload queue
+    async fn auto_upload(&self, session: SessionEnd) {
+        let rule = match self
+            .config
+            .read()
+            .await
+            .auto_upload_rules
+            .get(&session.room_id.to_string())
+        {
+            Some(rule) if rule.enabled => rule.clone(),
+            _ => return,
+        };
+        log::info!(
+            "Auto upload archive {} of room {}",
+            session.live_id,
+            session.room_id
+        );
+        match self.auto_upload_session(session, &rule).await {
+            Ok(content) => {
+                let _ = self.db.new_message("自动投稿", &content).await;
+            }
+            Err(e) => {
+                log::error!("Auto upload archive {} failed: {}", session.live_id, e);
+                let _ = self
+                    .db
+                    .new_message(
+                        "自动投稿失败",
+                        &format!(
+                            "房间 {} 的直播 {} 自动投稿失败：{}",
+                            session.room_id, session.live_id, e
+                        ),
+                    )
+                    .await;
+            }
+        }
+    }
+
+    async fn auto_upload_session(
+        &self,
+        session: SessionEnd,
+        rule: &AutoUploadRule,
+    ) -> Result<String, String> {
+        let room_id = session.room_id;
+        let live_id = session.live_id;
+        let record = self.recorder_manager.get_archive(room_id, live_id).await?;
+        // every segment is taken as 1s
+        let length = record.length.max(0) as u64;
+        if length == 0 || length < rule.min_length {
+            return Ok(format!(
+                "房间 {} 的直播 {} 时长 {} 秒，短于 {} 秒，跳过自动投稿",
+                room_id, live_id, length, rule.min_length
+            ));
+        }
+        // check account before clipping, or it only fails later in the queue
+        let uid = self.config.read().await.primary_uid;
+        if uid == 0 {
+            return Err("未设置主账号".to_string());
+        }
+        if let Err(e) = self.db.get_account(uid).await {
+            return Err(format!("主账号 {} 不可用：{}", uid, e));
+        }
+        let mut parts = Vec::new();
+        let mut cover = String::new();
+        for (x, y) in rule.split(length) {
+            let video = self
+                .clip_range("", room_id, live_id, x, y, &ClipOptions::default())
+                .await?;
+            if cover.is_empty() {
+                let file = format!("{}/{}", self.config.read().await.output, video.file);
+                cover = cover::extract_candidates(&file, 1)
+                    .into_iter()
+                    .next()
+                    .unwrap_or_default();
+            }
+            parts.push(UploadPart {
+                video_id: video.id,
+                title: "".to_string(),
+            });
+        }
+        if cover.is_empty() {
+            return Err("Extract cover failed".to_string());
+        }
+        let streamer = self
+            .recorder_manager
+            .get_recorder_info(room_id)
+            .await
+            .map(|info| info.user_info.user_name)
+            .unwrap_or_default();
+        let profile = self
+            .upload_manager
+            .render_profile(parts[0].video_id, rule.template_id, &streamer)
+            .await?;
+        let count = parts.len();
+        self.upload_manager
+            .enqueue(uid, room_id, parts, cover, &profile, None)
+            .await?;
+        Ok(format!(
+            "房间 {} 的直播 {} 已切分为 {} 个分P，加入投稿队列：{}",
+            room_id, live_id, count, profile.title
+        ))
+    }
+
+    /// Add clip file generated by recorder into videos
+    async fn save_clip(
+        &self,
+        cover: &str,
+        room_id: u64,
+        clip: &ClipResult,
+        length: f64,
+    ) -> Result<VideoRow, String> {
+        let file = &clip.file;
+        // get file metadata from fs
+        let metadata = std::fs::metadata(file).map_err(|e| e.to_string())?;
+        // get filename from path
+        let filename = Path::new(file)
+            .file_name()
+            .ok_or("Invalid file path")?
+            .to_str()
+            .ok_or("Invalid file path")?;
+        // add video to db
+        let video = self
+            .db
+            .add_video(
+                room_id,
+                cover,
+                filename,
+                length as i64,
+                metadata.len() as i64,
+                0,
+                "",
+                "",
+                "",
+                "",
+                0,
+                ClipFormat::from_file(filename).name(),
+                clip.loudness
+                    .as_ref()
+                    .and_then(|l| serde_json::to_string(l).ok()),
+                Some(clip.live_id),
+            )
+            .await?;
+        self
+            .db
+            .new_message(
+                "生成新切片",
+                &format!(
+                    "生成了房间 {} 的切片，长度 {:.1}s：{}",
+                    room_id,
+                    length,
+                    filename
+                ),
+            )
+            .await?;
+        if self.config.read().await.clip_notify {
+            self.app_handle.notification().builder().title("BiliShadowReplay - 切片完成").body(format!("生成了房间 {} 的切片: {}", room_id, filename)).show().unwrap();
+        }
+        Ok(video)
     }
 }
 
@@ -391,6 +731,109 @@ async fn update_notify(state: tauri::State<'_, State>, live_start_notify: bool,
     Ok(())
 }
 
+#[tauri::command]
+async fn get_encoding_presets(
+    state: tauri::State<'_, State>,
+) -> Result<Vec<EncodingPreset>, ()> {
+    Ok(state.config.read().await.encoding_presets.clone())
+}
+
+#[tauri::command]
+async fn set_encoding_preset(
+    state: tauri::State<'_, State>,
+    preset: EncodingPreset,
+) -> Result<(), String> {
+    state.config.write().await.set_encoding_preset(preset)
+}
+
+#[tauri::command]
+async fn remove_encoding_preset(state: tauri::State<'_, State>, name: String) -> Result<(), ()> {
+    state.config.write().await.remove_encoding_preset(&name);
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_room_preset(
+    state: tauri::State<'_, State>,
+    room_id: u64,
+    name: Option<String>,
+) -> Result<(), String> {
+    state.config.write().await.set_room_preset(room_id, name)
+}
+
+#[tauri::command]
+async fn set_auto_upload_rule(
+    state: tauri::State<'_, State>,
+    room_id: u64,
+    rule: Option<AutoUploadRule>,
+) -> Result<(), String> {
+    if let Some(id) = rule.as_ref().and_then(|r| r.template_id) {
+        state.db.get_profile_template(id).await?;
+    }
+    state.config.write().await.set_auto_upload_rule(room_id, rule)
+}
+
+#[tauri::command]
+async fn set_name_templates(
+    state: tauri::State<'_, State>,
+    clip: String,
+    archive: String,
+) -> Result<(), String> {
+    state
+        .config
+        .write()
+        .await
+        .set_name_templates(&clip, &archive)
+}
+
+#[tauri::command]
+async fn set_clip_sidecar(state: tauri::State<'_, State>, enabled: bool) -> Result<(), ()> {
+    let mut config = state.config.write().await;
+    config.clip_sidecar = enabled;
+    config.save();
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_title_font(state: tauri::State<'_, State>, path: String) -> Result<(), String> {
+    if !path.is_empty() && !Path::new(&path).exists() {
+        return Err(format!("Font {} not found", path));
+    }
+    let mut config = state.config.write().await;
+    config.title_font = path;
+    config.save();
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_upload_options(
+    state: tauri::State<'_, State>,
+    workers: usize,
+    retries: u32,
+    concurrency: usize,
+    max_attempts: u32,
+) -> Result<(), String> {
+    if !(1..=8).contains(&workers) {
+        return Err(format!("Upload workers {} is out of range [1, 8]", workers));
+    }
+    if retries > 10 {
+        return Err(format!("Upload retries {} is out of range [0, 10]", retries));
+    }
+    if !(1..=4).contains(&concurrency) {
+        return Err(format!("Upload concurrency {} is out of range [1, 4]", concurrency));
+    }
+    if !(1..=10).contains(&max_attempts) {
+        return Err(format!("Upload attempts {} is out of range [1, 10]", max_attempts));
+    }
+    let mut config = state.config.write().await;
+    config.upload_workers = workers;
+    config.upload_retries = retries;
+    config.upload_concurrency = concurrency;
+    config.upload_max_attempts = max_attempts;
+    config.save();
+    Ok(())
+}
+
 #[tauri::command]
 async fn set_output_path(state: tauri::State<'_, State>, output_path: String) -> Result<(), ()> {
     let mut config = state.config.write().await;
@@ -422,61 +865,336 @@ async fn clip_range(
     ts: u64,
     x: f64,
     y: f64,
+    options: Option<ClipOptions>,
+) -> Result<VideoRow, String> {
+    state
+        .clip_range(&cover, room_id, ts, x, y, &options.unwrap_or_default())
+        .await
+}
+
+/// start and end are wall-clock time in milliseconds
+#[tauri::command]
+async fn clip_time_range(
+    state: tauri::State<'_, State>,
+    cover: String,
+    room_id: u64,
+    start: i64,
+    end: i64,
+    options: Option<ClipOptions>,
+) -> Result<VideoRow, String> {
+    log::info!(
+        "Clip room_id: {}, time range: [{}, {}]",
+        room_id,
+        start,
+        end
+    );
+    let clip = state
+        .recorder_manager
+        .clip_time_range(
+            &state.config.read().await.output,
+            room_id,
+            start,
+            end,
+            &options.unwrap_or_default(),
+        )
+        .await?;
+    state
+        .save_clip(&cover, room_id, &clip, (end - start).abs() as f64 / 1000.0)
+        .await
+}
+
+/// x and y are relative to the timeline merged from sessions in live_ids
+#[tauri::command]
+async fn clip_merged_range(
+    state: tauri::State<'_, State>,
+    cover: String,
+    room_id: u64,
+    live_ids: Vec<u64>,
+    x: f64,
+    y: f64,
+    options: Option<ClipOptions>,
 ) -> Result<VideoRow, String> {
     log::info!(
-        "Clip room_id: {}, ts: {}, start: {}, end: {}",
+        "Clip room_id: {}, sessions: {:?}, start: {}, end: {}",
         room_id,
-        ts,
+        live_ids,
         x,
         y
     );
-    let file = state
+    let clip = state
         .recorder_manager
-        .clip_range(&state.config.read().await.output, room_id, ts, x, y)
+        .clip_merged_range(
+            &state.config.read().await.output,
+            room_id,
+            &live_ids,
+            x,
+            y,
+            &options.unwrap_or_default(),
+        )
         .await?;
-    // get file metadata from fs
-    let metadata = std::fs::metadata(&file).map_err(|e| e.to_string())?;
-    // get filename from path
-    let filename = Path::new(&file)
-        .file_name()
-        .ok_or("Invalid file path")?
-        .to_str()
-        .ok_or("Invalid file path")?;
-    // add video to db
-    let video = state
-        .db
-        .add_video(
+    state.save_clip(&cover, room_id, &clip, (y - x).abs()).await
+}
+
+/// Ranges are stitched in order into one clip
+#[tauri::command]
+async fn clip_compilation(
+    state: tauri::State<'_, State>,
+    cover: String,
+    room_id: u64,
+    ranges: Vec<ClipRange>,
+    compilation: Option<CompilationOptions>,
+    options: Option<ClipOptions>,
+) -> Result<VideoRow, String> {
+    log::info!(
+        "Clip compilation room_id: {}, ranges: {:?}",
+        room_id,
+        ranges
+    );
+    let (clip, length) = state
+        .recorder_manager
+        .clip_compilation(
+            &state.config.read().await.output,
             room_id,
-            &cover,
-            filename,
-            (y - x) as i64,
-            metadata.len() as i64,
-            0,
-            "",
-            "",
-            "",
-            "",
-            0,
+            &ranges,
+            &compilation.unwrap_or_default(),
+            &options.unwrap_or_default(),
         )
         .await?;
+    state.save_clip(&cover, room_id, &clip, length).await
+}
+
+#[tauri::command]
+async fn add_bookmark(
+    state: tauri::State<'_, State>,
+    room_id: u64,
+    live_id: u64,
+    offset: Option<f64>,
+    note: String,
+) -> Result<BookmarkRow, String> {
+    let offset = match offset {
+        Some(offset) => offset,
+        None => {
+            // bookmark the current live edge
+            let info = state
+                .recorder_manager
+                .get_recorder_info(room_id)
+                .await
+                .ok_or("Recorder not found")?;
+            if !info.live_status || info.current_ts != live_id {
+                return Err("Live is not recording, offset is required".into());
+            }
+            info.total_length
+        }
+    };
+    log::info!(
+        "Add bookmark room_id: {}, live_id: {}, offset: {}",
+        room_id,
+        live_id,
+        offset
+    );
+    Ok(state
+        .db
+        .add_bookmark(room_id, live_id, offset, &note)
+        .await?)
+}
+
+#[tauri::command]
+async fn list_bookmarks(
+    state: tauri::State<'_, State>,
+    room_id: u64,
+    live_id: u64,
+) -> Result<Vec<BookmarkRow>, String> {
+    Ok(state.db.get_bookmarks(room_id, live_id).await?)
+}
+
+#[tauri::command]
+async fn delete_bookmark(state: tauri::State<'_, State>, id: i64) -> Result<(), String> {
+    Ok(state.db.delete_bookmark(id).await?)
+}
+
+/// Clip the range between two bookmarks of the same live
+#[tauri::command]
+async fn clip_bookmark_range(
+    state: tauri::State<'_, State>,
+    cover: String,
+    start_id: i64,
+    end_id: i64,
+    options: Option<ClipOptions>,
+) -> Result<VideoRow, String> {
+    let start = state.db.get_bookmark(start_id).await?;
+    let end = state.db.get_bookmark(end_id).await?;
+    if start.room_id != end.room_id || start.live_id != end.live_id {
+        return Err("Bookmarks belong to different lives".into());
+    }
+    let (x, y) = if start.offset <= end.offset {
+        (start.offset, end.offset)
+    } else {
+        (end.offset, start.offset)
+    };
     state
+        .clip_range(
+            &cover,
+            start.room_id,
+            start.live_id,
+            x,
+            y,
+            &options.unwrap_or_default(),
+        )
+        .await
+}
+
+/// Save a vertical layout template for the room
+#[tauri::command]
+async fn add_layout(
+    state: tauri::State<'_, State>,
+    room_id: u64,
+    name: String,
+    width: u32,
+    height: u32,
+    blur_background: bool,
+    regions: Vec<CropRegion>,
+) -> Result<LayoutRow, String> {
+    let layout = VerticalLayout {
+        width,
+        height,
+        regions,
+        blur_background,
+    };
+    layout.validate()?;
+    let regions = serde_json::to_string(&layout.regions).map_err(|e| e.to_string())?;
+    Ok(state
         .db
-        .new_message(
-            "生成新切片",
-            &format!(
-                "生成了房间 {} 的切片，长度 {:.1}s：{}",
-                room_id,
-                y - x,
-                filename
-            ),
+        .add_layout(
+            room_id,
+            &name,
+            width as i64,
+            height as i64,
+            blur_background,
+            &regions,
         )
-        .await?;
-    if state.config.read().await.clip_notify {
-        state.app_handle.notification().builder().title("BiliShadowReplay - 切片完成").body(format!("生成了房间 {} 的切片: {}", room_id, filename)).show().unwrap();
+        .await?)
+}
+
+#[tauri::command]
+async fn list_layouts(
+    state: tauri::State<'_, State>,
+    room_id: u64,
+) -> Result<Vec<LayoutRow>, String> {
+    Ok(state.db.get_layouts(room_id).await?)
+}
+
+#[tauri::command]
+async fn delete_layout(state: tauri::State<'_, State>, id: i64) -> Result<(), String> {
+    Ok(state.db.delete_layout(id).await?)
+}
+
+#[tauri::command]
+async fn generate_covers(
+    state: tauri::State<'_, State>,
+    video_id: i64,
+    count: Option<usize>,
+) -> Result<Vec<String>, String> {
+    let video = state.db.get_video(video_id).await?;
+    let file = format!("{}/{}", state.config.read().await.output, video.file);
+    let count = count.unwrap_or(cover::DEFAULT_CANDIDATES).clamp(1, 20);
+    Ok(cover::extract_candidates(&file, count))
+}
+
+#[tauri::command]
+async fn get_covers(state: tauri::State<'_, State>, video_id: i64) -> Result<Vec<String>, String> {
+    let video = state.db.get_video(video_id).await?;
+    let file = format!("{}/{}", state.config.read().await.output, video.file);
+    Ok(cover::candidates(&file))
+}
+
+/// Use local image as cover of video, it is cropped to 16:9 and saved next to the clip, returns its path
+#[tauri::command]
+async fn set_video_cover(
+    state: tauri::State<'_, State>,
+    video_id: i64,
+    path: String,
+) -> Result<String, String> {
+    if !cover::is_image(&path) {
+        return Err(format!("{} is not an image file", path));
     }
-    Ok(video)
+    let video = state.db.get_video(video_id).await?;
+    let file = format!("{}/{}", state.config.read().await.output, video.file);
+    let cover = tokio::task::spawn_blocking(move || cover::save(&path, &file))
+        .await
+        .map_err(|e| e.to_string())??;
+    state.db.update_video_cover(video_id, &cover).await?;
+    Ok(cover)
+}
+
+#[tauri::command]
+async fn get_profile_templates(
+    state: tauri::State<'_, State>,
+) -> Result<Vec<ProfileTemplateRow>, String> {
+    Ok(state.db.get_profile_templates().await?)
+}
+
+/// Add template, or update the one with the same id if id is not 0
+#[tauri::command]
+async fn save_profile_template(
+    state: tauri::State<'_, State>,
+    template: ProfileTemplateRow,
+) -> Result<ProfileTemplateRow, String> {
+    Profile::validate_template(&template)?;
+    if template.id == 0 {
+        return Ok(state.db.add_profile_template(&template).await?);
+    }
+    state.db.update_profile_template(&template).await?;
+    Ok(state.db.get_profile_template(template.id).await?)
+}
+
+#[tauri::command]
+async fn delete_profile_template(state: tauri::State<'_, State>, id: i64) -> Result<(), String> {
+    state.db.delete_profile_template(id).await?;
+    let mut config = state.config.write().await;
+    // rooms using it have no default template anymore
+    config.room_profile_templates.retain(|_, t| *t != id);
+    config.save();
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_room_profile_template(
+    state: tauri::State<'_, State>,
+    room_id: u64,
+    id: Option<i64>,
+) -> Result<(), String> {
+    if let Some(id) = id {
+        state.db.get_profile_template(id).await?;
+    }
+    state
+        .config
+        .write()
+        .await
+        .set_room_profile_template(room_id, id);
+    Ok(())
 }
 
+/// Profile of video rendered from template, template_id defaults to the template of its room
+#[tauri::command]
+async fn render_profile(
+    state: tauri::State<'_, State>,
+    video_id: i64,
+    template_id: Option<i64>,
+) -> Result<Profile, String> {
+    let video = state.db.get_video(video_id).await?;
+    let streamer = state
+        .recorder_manager
+        .get_recorder_info(video.room_id)
+        .await
+        .map(|info| info.user_info.user_name)
+        .unwrap_or_default();
+    Ok(state
+        .upload_manager
+        .render_profile(video_id, template_id, &streamer)
+        .await?)
+}
+
+/// Add submission of video into upload queue, it is held until start_at in milliseconds if set
 #[tauri::command]
 async fn upload_procedure(
     state: tauri::State<'_, State>,
@@ -484,49 +1202,60 @@ async fn upload_procedure(
     room_id: u64,
     video_id: i64,
     cover: String,
-    mut profile: Profile,
-) -> Result<String, String> {
-    let account = state.db.get_account(uid).await?;
-    // get video info from dbs
-    let video = state.db.get_video(video_id).await?;
-    // construct file path
-    let output = state.config.read().await.output.clone();
-    let file = format!("{}/{}", output, video.file);
-    let path = Path::new(&file);
-    let cover_url = state.client.upload_cover(&account, &cover);
-    if let Ok(video) = state.client.prepare_video(&account, path).await {
-        profile.cover = cover_url.await.unwrap_or("".to_string());
-        if let Ok(ret) = state.client.submit_video(&account, &profile, &video).await {
-            // update video status and details
-            // 1 means uploaded
-            state
-                .db
-                .update_video(
-                    video_id,
-                    1,
-                    &ret.bvid,
-                    &profile.title,
-                    &profile.desc,
-                    &profile.tag,
-                    profile.tid,
-                )
-                .await?;
-            state
-                .db
-                .new_message(
-                    "投稿成功",
-                    &format!("投稿了房间 {} 的切片：{}", room_id, ret.bvid),
-                )
-                .await?;
-            if state.config.read().await.post_notify {
-                state.app_handle.notification().builder().title("BiliShadowReplay - 投稿成功").body(format!("投稿了房间 {} 的切片: {}", room_id, ret.bvid)).show().unwrap();
-            }
-            Ok(ret.bvid)
-        } else {
-            Err("Submit video failed".to_string())
-        }
+    profile: Profile,
+    start_at: Option<i64>,
+) -> Result<UploadTaskRow, String> {
+    let parts = vec![UploadPart {
+        video_id,
+        title: "".to_string(),
+    }];
+    Ok(state
+        .upload_manager
+        .enqueue(uid, room_id, parts, cover, &profile, start_at)
+        .await?)
+}
+
+/// Add submission of several videos as parts into upload queue
+#[tauri::command]
+async fn upload_parts(
+    state: tauri::State<'_, State>,
+    uid: u64,
+    room_id: u64,
+    parts: Vec<UploadPart>,
+    cover: String,
+    profile: Profile,
+    start_at: Option<i64>,
+) -> Result<UploadTaskRow, String> {
+    Ok(state
+        .upload_manager
+        .enqueue(uid, room_id, parts, cover, &profile, start_at)
+        .await?)
+}
+
+#[tauri::command]
+async fn get_upload_tasks(state: tauri::State<'_, State>) -> Result<Vec<UploadTaskRow>, String> {
+    Ok(state.upload_manager.get_tasks().await?)
+}
+
+#[tauri::command]
+async fn retry_upload(state: tauri::State<'_, State>, id: i64) -> Result<UploadTaskRow, String> {
+    Ok(state.upload_manager.retry(id).await?)
+}
+
+#[tauri::command]
+async fn cancel_upload(state: tauri::State<'_, State>, id: i64) -> Result<(), String> {
+    Ok(state.upload_manager.cancel(id).await?)
+}
+
+#[tauri::command]
+async fn get_upload_status(
+    state: tauri::State<'_, State>,
+    video_id: i64,
+) -> Result<UploadProgress, String> {
+    if let Some(progress) = state.upload_manager.get_progress(video_id).await {
+        Ok(progress)
     } else {
-        Err("Preload video failed".to_string())
+        Err("Not found".to_string())
     }
 }
 
@@ -551,6 +1280,14 @@ async fn get_archives(
     Ok(state.recorder_manager.get_archives(room_id).await?)
 }
 
+#[tauri::command]
+async fn get_merged_archives(
+    state: tauri::State<'_, State>,
+    room_id: u64,
+) -> Result<Vec<MergedSession>, String> {
+    Ok(state.recorder_manager.get_merged_archives(room_id).await?)
+}
+
 #[tauri::command]
 async fn get_archive(
     state: tauri::State<'_, State>,
@@ -560,6 +1297,44 @@ async fn get_archive(
     Ok(state.recorder_manager.get_archive(room_id, live_id).await?)
 }
 
+/// Path of trickplay WebVTT of archive on HLS server, media playlists can not carry it
+#[tauri::command]
+async fn get_trickplay(
+    state: tauri::State<'_, State>,
+    room_id: u64,
+    live_id: u64,
+) -> Result<Option<String>, String> {
+    Ok(state.recorder_manager.get_trickplay(room_id, live_id).await?)
+}
+
+/// Wall-clock time in milliseconds of an archive offset
+#[tauri::command]
+async fn archive_offset_to_time(
+    state: tauri::State<'_, State>,
+    room_id: u64,
+    live_id: u64,
+    offset: f64,
+) -> Result<i64, String> {
+    Ok(state
+        .recorder_manager
+        .offset_to_time(room_id, live_id, offset)
+        .await?)
+}
+
+/// Archive offset of a wall-clock time in milliseconds
+#[tauri::command]
+async fn archive_time_to_offset(
+    state: tauri::State<'_, State>,
+    room_id: u64,
+    live_id: u64,
+    time: i64,
+) -> Result<f64, String> {
+    Ok(state
+        .recorder_manager
+        .time_to_offset(room_id, live_id, time)
+        .await?)
+}
+
 #[tauri::command]
 async fn delete_archive(
     state: tauri::State<'_, State>,
@@ -567,6 +1342,7 @@ async fn delete_archive(
     ts: u64,
 ) -> Result<(), String> {
     state.recorder_manager.delete_archive(room_id, ts).await;
+    state.db.remove_bookmarks(ts).await?;
     state
         .db
         .new_message(
@@ -702,6 +1478,10 @@ async fn delete_video(state: tauri::State<'_, State>, id: i64) -> Result<(), Str
     if let Err(e) = std::fs::remove_file(file) {
         log::error!("Delete video file error: {}", e);
     }
+    cover::remove_candidates(&filepath);
+    let _ = std::fs::remove_file(cover::cover_file(&filepath));
+    state.db.remove_upload(id).await?;
+    state.db.remove_upload_tasks(id).await?;
     Ok(state.db.delete_video(id).await?)
 }
 
@@ -719,10 +1499,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     ffmpeg_sidecar::download::auto_download().unwrap();
 
     //Setup database
-    let migrations = vec![Migration {
-        version: 1,
-        description: "create_initial_tables",
-        sql: r#"
+    let migrations = vec![
+        Migration {
+            version: 1,
+            description: "create_initial_tables",
+            sql: r#"
             CREATE TABLE accounts (uid INTEGER PRIMARY KEY, name TEXT, avatar TEXT, csrf TEXT, cookies TEXT, created_at TEXT);
             CREATE TABLE recorders (room_id INTEGER PRIMARY KEY, created_at TEXT);
             CREATE TABLE records (live_id INTEGER PRIMARY KEY, room_id INTEGER, title TEXT, length INTEGER, size INTEGER, created_at TEXT);
@@ -730,8 +1511,99 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             CREATE TABLE messages (id INTEGER PRIMARY KEY AUTOINCREMENT, title TEXT, content TEXT, read INTEGER, created_at TEXT);
             CREATE TABLE videos (id INTEGER PRIMARY KEY AUTOINCREMENT, room_id INTEGER, cover TEXT, file TEXT, length INTEGER, size INTEGER, status INTEGER, bvid TEXT, title TEXT, desc TEXT, tags TEXT, area INTEGER, created_at TEXT);
             "#,
-        kind: MigrationKind::Up,
-    }];
+            kind: MigrationKind::Up,
+        },
+        Migration {
+            version: 2,
+            description: "create_bookmarks_table",
+            sql: r#"
+            CREATE TABLE bookmarks (id INTEGER PRIMARY KEY AUTOINCREMENT, room_id INTEGER, live_id INTEGER, offset REAL, note TEXT, created_at TEXT);
+            "#,
+            kind: MigrationKind::Up,
+        },
+        Migration {
+            version: 3,
+            description: "create_layouts_table",
+            sql: r#"
+            CREATE TABLE layouts (id INTEGER PRIMARY KEY AUTOINCREMENT, room_id INTEGER, name TEXT, width INTEGER, height INTEGER, blur_background INTEGER, regions TEXT, created_at TEXT);
+            "#,
+            kind: MigrationKind::Up,
+        },
+        Migration {
+            version: 4,
+            description: "add_video_format",
+            sql: r#"
+            ALTER TABLE videos ADD COLUMN format TEXT NOT NULL DEFAULT 'mp4';
+            "#,
+            kind: MigrationKind::Up,
+        },
+        Migration {
+            version: 5,
+            description: "add_video_loudness",
+            sql: r#"
+            ALTER TABLE videos ADD COLUMN loudness TEXT;
+            "#,
+            kind: MigrationKind::Up,
+        },
+        Migration {
+            version: 6,
+            description: "create_uploads_table",
+            sql: r#"
+            CREATE TABLE uploads (video_id INTEGER PRIMARY KEY, state TEXT, updated_at TEXT);
+            "#,
+            kind: MigrationKind::Up,
+        },
+        Migration {
+            version: 7,
+            description: "create_upload_tasks_table",
+            sql: r#"
+            CREATE TABLE upload_tasks (id INTEGER PRIMARY KEY AUTOINCREMENT, video_id INTEGER, uid INTEGER, room_id INTEGER, cover TEXT, profile TEXT, status TEXT, attempts INTEGER, error TEXT, bvid TEXT, next_retry_at INTEGER, created_at TEXT, updated_at TEXT);
+            "#,
+            kind: MigrationKind::Up,
+        },
+        Migration {
+            version: 8,
+            description: "add_upload_task_parts",
+            sql: r#"
+            ALTER TABLE upload_tasks ADD COLUMN parts TEXT NOT NULL DEFAULT '[]';
+            "#,
+            kind: MigrationKind::Up,
+        },
+        Migration {
+            version: 9,
+            description: "create_profile_templates_table",
+            sql: r#"
+            CREATE TABLE profile_templates (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT, title TEXT, tid INTEGER, tag TEXT, desc TEXT, copyright INTEGER, dynamic TEXT, created_at TEXT);
+            "#,
+            kind: MigrationKind::Up,
+        },
+        Migration {
+            version: 10,
+            description: "add_video_review_state",
+            sql: r#"
+            ALTER TABLE videos ADD COLUMN review_state INTEGER;
+            ALTER TABLE videos ADD COLUMN review_desc TEXT;
+            ALTER TABLE videos ADD COLUMN reject_reason TEXT;
+            "#,
+            kind: MigrationKind::Up,
+        },
+        Migration {
+            version: 11,
+            description: "add_video_live_id",
+            sql: r#"
+            ALTER TABLE videos ADD COLUMN live_id INTEGER;
+            "#,
+            kind: MigrationKind::Up,
+        },
+        Migration {
+            version: 12,
+            description: "add_upload_task_cover43",
+            sql: r#"
+            ALTER TABLE upload_tasks ADD COLUMN cover43 TEXT;
+            "#,
+            kind: MigrationKind::Up,
+        },
+    ];
 
     // Tauri part
     tauri::Builder::default()
@@ -752,8 +1624,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             let client = Arc::new(BiliClient::new().unwrap());
             let config = Arc::new(RwLock::new(Config::load()));
             let config_clone = config.clone();
-            let recorder_manager =
-                Arc::new(RecorderManager::new(app.handle().clone(), config.clone()));
+            let (session_end, mut ended_sessions) = tokio::sync::mpsc::unbounded_channel();
+            let recorder_manager = Arc::new(RecorderManager::new(
+                app.handle().clone(),
+                client.clone(),
+                config.clone(),
+                session_end,
+            ));
             let recorder_manager_clone = recorder_manager.clone();
             let dbs = app.state::<tauri_plugin_sql::DbInstances>().inner();
             let db = Arc::new(Database::new());
@@ -829,13 +1706,34 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     log::warn!("No available account found");
                 }
             });
+            let upload_manager = Arc::new(UploadManager::new(
+                app.handle().clone(),
+                db.clone(),
+                client.clone(),
+                config.clone(),
+            ));
+            let upload_manager_clone = upload_manager.clone();
+            tauri::async_runtime::spawn(async move {
+                upload_manager_clone.run().await;
+            });
+            let recorder_manager_clone = recorder_manager.clone();
+            tauri::async_runtime::spawn(async move {
+                recorder_manager_clone.run_status_poller().await;
+            });
             let state = State {
                 db,
                 client,
                 config,
                 recorder_manager,
                 app_handle: app.handle().clone(),
+                upload_manager,
             };
+            let state_clone = state.clone();
+            tauri::async_runtime::spawn(async move {
+                while let Some(session) = ended_sessions.recv().await {
+                    state_clone.auto_upload(session).await;
+                }
+            });
             let _ = tray::create_tray(app.handle());
             app.manage(state);
             Ok(())
@@ -857,7 +1755,28 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             set_output_path,
             clip,
             clip_range,
+            clip_time_range,
+            clip_merged_range,
+            clip_compilation,
+            add_bookmark,
+            list_bookmarks,
+            delete_bookmark,
+            clip_bookmark_range,
+            add_layout,
+            list_layouts,
+            delete_layout,
             upload_procedure,
+            upload_parts,
+            get_profile_templates,
+            save_profile_template,
+            delete_profile_template,
+            set_room_profile_template,
+            set_auto_upload_rule,
+            render_profile,
+            get_upload_status,
+            get_upload_tasks,
+            retry_upload,
+            cancel_upload,
             show_in_folder,
             get_qr,
             get_qr_status,
@@ -868,7 +1787,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             set_primary,
             get_room_info,
             get_archive,
+            get_trickplay,
             get_archives,
+            get_merged_archives,
+            archive_offset_to_time,
+            archive_time_to_offset,
             delete_archive,
             get_messages,
             read_message,
@@ -879,6 +1802,17 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             get_disk_info,
             send_danmaku,
             update_notify,
+            get_encoding_presets,
+            set_encoding_preset,
+            remove_encoding_preset,
+            set_room_preset,
+            set_name_templates,
+            set_clip_sidecar,
+            set_title_font,
+            set_upload_options,
+            generate_covers,
+            get_covers,
+            set_video_cover,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
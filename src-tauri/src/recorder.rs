@@ -1,7 +1,21 @@
 pub mod bilibili;
+pub mod cover;
+pub mod encoding;
+pub mod format;
+pub mod layout;
+pub mod loudness;
+pub mod metadata;
+pub mod naming;
+pub mod trickplay;
 use async_std::{fs, stream::StreamExt};
 use bilibili::{errors::BiliClientError, RoomInfo};
 use bilibili::{BiliClient, UserInfo};
+use encoding::EncodingPreset;
+use format::{AnimationOptions, ClipFormat};
+use layout::VerticalLayout;
+use loudness::{LoudnessReport, LoudnormOptions};
+use metadata::{ClipMetadata, ClipSource};
+use naming::NameContext;
 use chrono::prelude::*;
 use custom_error::custom_error;
 use felgens::{ws_socket_object, FelgensError, WsStreamMessageType};
@@ -13,6 +27,8 @@ use futures::future::join_all;
 use m3u8_rs::Playlist;
 use regex::Regex;
 use tauri_plugin_notification::NotificationExt;
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
 use std::sync::Arc;
 use std::thread;
 use tauri::{AppHandle, Emitter};
@@ -22,12 +38,222 @@ use tokio::sync::{Mutex, RwLock};
 use crate::db::{AccountRow, Database, DatabaseError, RecordRow};
 use crate::Config;
 
+/// Fetch time of every segment is appended into this file in the work dir, as `{sequence} {ms}` lines
+const TIMELINE_FILE: &str = "timeline.txt";
+
+/// Sessions are merged into one timeline if gap between them is within this
+const MERGE_THRESHOLD_MS: i64 = 10 * 60 * 1000;
+
+/// One session in merged timeline, start and end are wall-clock time in milliseconds
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct SessionSpan {
+    pub live_id: u64,
+    pub title: String,
+    pub start: i64,
+    pub end: i64,
+    pub length: i64,
+}
+
+/// Adjacent sessions of one broadcast, split when connection drops
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct MergedSession {
+    pub start: i64,
+    pub end: i64,
+    pub length: i64,
+    pub sessions: Vec<SessionSpan>,
+}
+
+/// Options for clip output, provided along with every clip request
+#[derive(serde::Deserialize, Clone, Debug, Default)]
+pub struct ClipOptions {
+    /// name of encoding preset in config, default preset of the room is used if None
+    #[serde(default)]
+    pub preset: Option<String>,
+    /// id of saved layout, output is a vertical short video if set
+    #[serde(default)]
+    pub layout: Option<i64>,
+    /// file name template for this clip, template in config is used if None
+    #[serde(default)]
+    pub name_template: Option<String>,
+    #[serde(default)]
+    pub format: ClipFormat,
+    /// options for GIF and WebP, defaults are used if None
+    #[serde(default)]
+    pub animation: Option<AnimationOptions>,
+    /// normalize loudness of clip audio if set
+    #[serde(default)]
+    pub loudnorm: Option<LoudnormOptions>,
+}
+
+/// Finished clip file
+#[derive(serde::Serialize, Clone, Debug)]
+pub struct ClipResult {
+    pub file: String,
+    /// first source archive of the clip
+    pub live_id: u64,
+    /// measured loudness if loudness normalization is enabled
+    pub loudness: Option<LoudnessReport>,
+}
+
+/// Resolved output of a clip
+#[derive(Clone)]
+struct ClipOutput {
+    preset: EncodingPreset,
+    layout: Option<VerticalLayout>,
+    format: ClipFormat,
+    animation: AnimationOptions,
+    /// applied on the final file, never set for animations
+    loudnorm: Option<LoudnormOptions>,
+    /// ffmpeg metadata arguments of the final file
+    metadata: Vec<String>,
+}
+
+impl ClipOutput {
+    /// ffmpeg output arguments for a single input
+    fn args(&self) -> Vec<String> {
+        match self.format {
+            ClipFormat::Mp4 => self.video_args(),
+            ClipFormat::Audio => [ClipFormat::audio_args(&self.preset), self.metadata.clone()].concat(),
+            ClipFormat::Gif | ClipFormat::Webp => {
+                let mut graph = String::new();
+                let mut input = "0:v";
+                if let Some(layout) = &self.layout {
+                    graph += &layout.filter_graph("0:v", "vout");
+                    graph += ";";
+                    input = "vout";
+                }
+                graph += &self.format.animation_filter(&self.animation, input, "anim");
+                let mut args = vec![
+                    "-filter_complex".to_string(),
+                    graph,
+                    "-map".to_string(),
+                    "[anim]".to_string(),
+                ];
+                args.extend(self.format.animation_args());
+                args
+            }
+        }
+    }
+
+    /// Same output in mp4, used for parts that are joined or converted later
+    fn intermediate(&self) -> ClipOutput {
+        ClipOutput {
+            format: ClipFormat::Mp4,
+            metadata: Vec::new(),
+            ..self.clone()
+        }
+    }
+
+    /// Converts intermediate mp4 into this format, layout is already applied on intermediate
+    fn conversion(&self) -> ClipOutput {
+        ClipOutput {
+            layout: None,
+            ..self.clone()
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        self.format.extension(&self.preset)
+    }
+
+    /// Check format specific limits before ffmpeg is spawned
+    fn check(&self, duration: f64) -> Result<(), RecorderError> {
+        self.format
+            .validate(&self.animation, duration)
+            .map_err(|err| RecorderError::InvalidFormat { err })
+    }
+
+    fn video_args(&self) -> Vec<String> {
+        let layout = match &self.layout {
+            Some(layout) => layout,
+            None => return [self.preset.output_args(), self.metadata.clone()].concat(),
+        };
+        let mut args = vec![
+            "-filter_complex".to_string(),
+            layout.filter_graph("0:v", "vout"),
+            "-map".to_string(),
+            "[vout]".to_string(),
+            "-map".to_string(),
+            "0:a?".to_string(),
+        ];
+        if let Some(fps) = self.preset.fps {
+            args.extend(["-r".to_string(), fps.to_string()]);
+        }
+        args.extend(self.preset.codec_args());
+        args.extend(self.metadata.clone());
+        args
+    }
+}
+
+/// One range in a compilation, offsets are relative to first sequence of live_id
+#[derive(serde::Deserialize, Clone, Debug)]
+pub struct ClipRange {
+    pub live_id: u64,
+    pub start: f64,
+    pub end: f64,
+    /// shown on title card before the range
+    #[serde(default)]
+    pub title: String,
+}
+
+#[derive(serde::Deserialize, Clone, Debug, Default)]
+pub struct CompilationOptions {
+    /// crossfade duration between ranges in seconds, 0 for hard cut
+    #[serde(default)]
+    pub transition: f64,
+    /// duration of title cards in seconds, 0 for no title card
+    #[serde(default)]
+    pub title_card: f64,
+}
+
+struct CompilationPart {
+    file: String,
+    duration: f64,
+    title: String,
+}
+
+/// Compilations are re-encoded into this canvas if preset does not specify, as ranges may come with different resolution
+const COMPILATION_WIDTH: u32 = 1920;
+const COMPILATION_HEIGHT: u32 = 1080;
+const COMPILATION_FPS: u32 = 30;
+/// Fonts tried for title cards if none is configured, CJK ones first as titles are mostly Chinese
+#[cfg(target_os = "windows")]
+const TITLE_FONTS: &[&str] = &[
+    "C:/Windows/Fonts/msyh.ttc",
+    "C:/Windows/Fonts/simhei.ttf",
+    "C:/Windows/Fonts/arial.ttf",
+];
+#[cfg(target_os = "macos")]
+const TITLE_FONTS: &[&str] = &[
+    "/System/Library/Fonts/PingFang.ttc",
+    "/System/Library/Fonts/STHeiti Medium.ttc",
+    "/System/Library/Fonts/Helvetica.ttc",
+];
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+const TITLE_FONTS: &[&str] = &[
+    "/usr/share/fonts/opentype/noto/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/noto-cjk/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/google-noto-cjk/NotoSansCJK-Regular.ttc",
+    "/usr/share/fonts/truetype/wqy/wqy-microhei.ttc",
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+];
+
+/// Sent when a live session of room ends and its archive is complete
+#[derive(Clone, Copy, Debug)]
+pub struct SessionEnd {
+    pub room_id: u64,
+    pub live_id: u64,
+}
+
 #[derive(Clone)]
 pub struct TsEntry {
     pub url: String,
     pub sequence: u64,
     pub _length: f64,
     pub size: u64,
+    /// fetch time of the segment in milliseconds, 0 if unknown
+    pub ts: i64,
 }
 
 /// A recorder for BiliBili live streams
@@ -55,6 +281,9 @@ pub struct BiliRecorder {
     header: Arc<RwLock<Option<TsEntry>>>,
     stream_type: Arc<RwLock<StreamType>>,
     cache_size: Arc<RwLock<u64>>,
+    /// live ids of sessions that trickplay sprites are being generated for
+    trickplay_tasks: Arc<Mutex<HashSet<u64>>>,
+    session_end: mpsc::UnboundedSender<SessionEnd>,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
@@ -71,6 +300,17 @@ custom_error! {pub RecorderError
     EmptyHeader = "Header url is empty",
     InvalidTimestamp = "Header timestamp is invalid",
     InvalidPlaylist = "Invalid m3u8 playlist",
+    InvalidOffset { offset: f64 } = "Offset {offset} is out of range",
+    TimeInGap { time: i64 } = "No segment is recorded at {time}",
+    InvalidTime { time: i64 } = "Time {time} is out of range",
+    MissingTimestamp = "Segment time is not recorded",
+    PresetNotFound { name: String } = "Encoding preset {name} not found",
+    InvalidPreset { err: String } = "Invalid encoding preset: {err}",
+    InvalidLayout { err: String } = "Invalid layout: {err}",
+    InvalidNameTemplate { err: String } = "Invalid name template: {err}",
+    InvalidFormat { err: String } = "Invalid clip format: {err}",
+    InvalidLoudnorm { err: String } = "Invalid loudness normalization: {err}",
+    FfmpegFailed { err: String } = "FFmpeg failed: {err}",
     InvalidDBOP {err: DatabaseError } = "Database error {err}",
     ClientError {err: BiliClientError} = "BiliClient fetch failed {err}",
 }
@@ -88,15 +328,19 @@ impl From<BiliClientError> for RecorderError {
 }
 
 impl BiliRecorder {
+    /// client is forked so stream url parameters are kept per room
+    #[allow(clippy::too_many_arguments)]
     pub async fn new(
         app_handle: AppHandle,
+        client: &BiliClient,
         webid: &str,
         db: &Arc<Database>,
         room_id: u64,
         account: &AccountRow,
         config: Arc<RwLock<Config>>,
+        session_end: mpsc::UnboundedSender<SessionEnd>,
     ) -> Result<Self, RecorderError> {
-        let client = BiliClient::new()?;
+        let client = client.fork();
         let room_info = client.get_room_info(account, room_id).await?;
         let user_info = client
             .get_user_info(webid, account, room_info.user_id)
@@ -133,6 +377,8 @@ impl BiliRecorder {
             header: Arc::new(RwLock::new(None)),
             stream_type: Arc::new(RwLock::new(stream_type)),
             cache_size: Arc::new(RwLock::new(0)),
+            trickplay_tasks: Arc::new(Mutex::new(HashSet::new())),
+            session_end,
         };
         log::info!("Recorder for room {} created.", room_id);
         Ok(recorder)
@@ -146,6 +392,7 @@ impl BiliRecorder {
         *self.timestamp.write().await = 0;
     }
 
+    /// Check live status of this room only, status of all rooms is polled by manager in batches
     async fn check_status(&self) -> bool {
         if let Ok(room_info) = self
             .client
@@ -154,30 +401,52 @@ impl BiliRecorder {
             .get_room_info(&self.account, self.room_id)
             .await
         {
-            *self.room_info.write().await = room_info.clone();
-            let live_status = room_info.live_status == 1;
-
-            // handle live notification
-            if *self.live_status.read().await != live_status {
-                if live_status {
-                    if self.config.read().await.live_start_notify {
-                        self.app_handle
-                            .notification()
-                            .builder()
-                            .title("BiliShadowReplay - 直播开始")
-                            .body(format!("{} 开启了直播：{}",self.user_info.read().await.user_name, room_info.room_title)).show().unwrap();
-                    }
-                } else if self.config.read().await.live_end_notify {
+            self.update_status(room_info, true).await
+        } else {
+            *self.live_status.write().await = true;
+            // may encouter internet issues, not sure whether the stream is closed
+            true
+        }
+    }
+
+    /// Apply room info fetched for this room, returns live status.
+    ///
+    /// Stream url is only fetched for live rooms, when the live just started, the url is missing
+    /// or refresh_url is set.
+    pub async fn update_status(&self, mut room_info: RoomInfo, refresh_url: bool) -> bool {
+        if room_info.room_keyframe_url.is_empty() {
+            // batch room info has no keyframe
+            room_info.room_keyframe_url = self.room_info.read().await.room_keyframe_url.clone();
+        }
+        *self.room_info.write().await = room_info.clone();
+        let live_status = room_info.live_status == 1;
+        // poller and recording thread may update at the same time, only the caller swapping the
+        // status sees the change
+        let was_live = std::mem::replace(&mut *self.live_status.write().await, live_status);
+
+        // handle live notification
+        if was_live != live_status {
+            if live_status {
+                if self.config.read().await.live_start_notify {
                     self.app_handle
                         .notification()
                         .builder()
-                        .title("BiliShadowReplay - 直播结束")
-                        .body(format!("{} 的直播结束了",self.user_info.read().await.user_name)).show().unwrap();
+                        .title("BiliShadowReplay - 直播开始")
+                        .body(format!("{} 开启了直播：{}",self.user_info.read().await.user_name, room_info.room_title)).show().unwrap();
                 }
+            } else if self.config.read().await.live_end_notify {
+                self.app_handle
+                    .notification()
+                    .builder()
+                    .title("BiliShadowReplay - 直播结束")
+                    .body(format!("{} 的直播结束了",self.user_info.read().await.user_name)).show().unwrap();
             }
-            // if stream is confirmed to be closed, live stream cache is cleaned.
-            // all request will go through fs
-            if live_status {
+        }
+        // if stream is confirmed to be closed, live stream cache is cleaned.
+        // all request will go through fs
+        if live_status {
+            // stream url is kept while recording
+            if refresh_url || !was_live || self.m3u8_url.read().await.is_empty() {
                 if let Ok((index_url, stream_type)) = self
                     .client
                     .read()
@@ -188,16 +457,20 @@ impl BiliRecorder {
                     self.m3u8_url.write().await.replace_range(.., &index_url);
                     *self.stream_type.write().await = stream_type;
                 }
-            } else {
-                self.reset().await;
             }
-            *self.live_status.write().await = live_status;
-            live_status
-        } else {
-            *self.live_status.write().await = true;
-            // may encouter internet issues, not sure whether the stream is closed
-            true
+        } else if was_live {
+            // sprites of the last sheet are generated once the session is finished
+            let timestamp = *self.timestamp.read().await;
+            if timestamp != 0 {
+                self.update_trickplay(timestamp, true).await;
+                let _ = self.session_end.send(SessionEnd {
+                    room_id: self.room_id,
+                    live_id: timestamp,
+                });
+            }
+            self.reset().await;
         }
+        live_status
     }
 
     pub async fn get_archives(&self) -> Result<Vec<RecordRow>, RecorderError> {
@@ -219,13 +492,87 @@ impl BiliRecorder {
         }
     }
 
+    /// Generate missing trickplay sprites of session live_id in background
+    async fn update_trickplay(&self, live_id: u64, finished: bool) {
+        let work_dir = format!("{}/{}/{}", self.config.read().await.cache, self.room_id, live_id);
+        let entries = self.get_entries(live_id).await;
+        if !trickplay::pending(&work_dir, entries.len(), finished) {
+            return;
+        }
+        if !self.trickplay_tasks.lock().await.insert(live_id) {
+            return;
+        }
+        let segments: Vec<String> = entries
+            .iter()
+            .map(|e| format!("{}/{}", work_dir, e.url.split('/').last().unwrap()))
+            .collect();
+        let header = format!("{}/h{}.m4s", work_dir, live_id);
+        let header = std::path::Path::new(&header).exists().then_some(header);
+        let tasks = self.trickplay_tasks.clone();
+        thread::spawn(move || {
+            trickplay::generate(&work_dir, header.as_deref(), &segments, finished);
+            tasks.blocking_lock().remove(&live_id);
+        });
+    }
+
+    /// Segments of the live, current live is read from memory
+    async fn get_entries(&self, live_id: u64) -> Vec<TsEntry> {
+        if *self.timestamp.read().await == live_id {
+            self.ts_entries.lock().await.clone()
+        } else {
+            let work_dir = format!("{}/{}/{}", self.config.read().await.cache, self.room_id, live_id);
+            self.get_fs_entries(&work_dir).await
+        }
+    }
+
+    /// Convert offset relative to first sequence into wall-clock time in milliseconds
+    pub async fn offset_to_time(&self, live_id: u64, offset: f64) -> Result<i64, RecorderError> {
+        let entries = self.get_entries(live_id).await;
+        if entries.is_empty() {
+            return Err(RecorderError::EmptyCache);
+        }
+        // every segment is taken as 1.0s
+        if offset < 0.0 || offset >= entries.len() as f64 {
+            return Err(RecorderError::InvalidOffset { offset });
+        }
+        let entry = &entries[offset as usize];
+        if entry.ts == 0 {
+            return Err(RecorderError::MissingTimestamp);
+        }
+        Ok(entry.ts + (offset.fract() * 1000.0) as i64)
+    }
+
+    /// Convert wall-clock time in milliseconds into offset relative to first sequence
+    pub async fn time_to_offset(&self, live_id: u64, time: i64) -> Result<f64, RecorderError> {
+        let entries = self.get_entries(live_id).await;
+        if entries.is_empty() {
+            return Err(RecorderError::EmptyCache);
+        }
+        if entries.iter().any(|e| e.ts == 0) {
+            return Err(RecorderError::MissingTimestamp);
+        }
+        let index = entries.partition_point(|e| e.ts <= time);
+        if index == 0 {
+            return Err(RecorderError::InvalidTime { time });
+        }
+        let diff = time - entries[index - 1].ts;
+        if index == entries.len() && diff >= 1000 {
+            return Err(RecorderError::InvalidTime { time });
+        }
+        // time inside a gap is clamped to the end of previous segment
+        Ok((index - 1) as f64 + diff.min(999) as f64 / 1000.0)
+    }
+
     pub async fn run(&self) {
         let self_clone = self.clone();
         thread::spawn(move || {
             let runtime = tokio::runtime::Runtime::new().unwrap();
             runtime.block_on(async move {
                 while !*self_clone.quit.lock().await {
-                    if self_clone.check_status().await {
+                    // live status is updated by the status poller of manager
+                    if *self_clone.live_status.read().await
+                        && !self_clone.m3u8_url.read().await.is_empty()
+                    {
                         // Live status is ok, start recording.
                         while !*self_clone.quit.lock().await {
                             if let Err(e) = self_clone.update_entries().await {
@@ -234,11 +581,11 @@ impl BiliRecorder {
                             }
                             thread::sleep(std::time::Duration::from_secs(1));
                         }
-                        // go check status again
+                        // stream may be closed, confirm it with status of this room
+                        self_clone.check_status().await;
                         continue;
                     }
-                    // Every 10s check live status.
-                    thread::sleep(std::time::Duration::from_secs(10));
+                    thread::sleep(std::time::Duration::from_secs(1));
                 }
                 log::info!("recording thread {} quit.", self_clone.room_id);
             });
@@ -402,6 +749,7 @@ impl BiliRecorder {
                 sequence: 0,
                 _length: 0.0,
                 size: 0,
+                ts: 0,
             };
             let file_name = header_url.split('/').last().unwrap();
             // Download header
@@ -428,16 +776,27 @@ impl BiliRecorder {
             Ok(Playlist::MediaPlaylist(pl)) => {
                 let mut sequence = pl.media_sequence;
                 let mut handles = Vec::new();
+                let last_length = self.ts_entries.lock().await.len();
+                let mut timeline = String::new();
+                // first new segment takes fetch time, following ones are placed after it by
+                // duration, PDT in playlist is preferred if present
+                let mut next_ts = Utc::now().timestamp_millis();
                 for ts in pl.segments {
                     if sequence <= *self.last_sequence.read().await {
                         sequence += 1;
                         continue;
                     }
+                    let segment_ts = ts
+                        .program_date_time
+                        .map(|pdt| pdt.timestamp_millis())
+                        .unwrap_or(next_ts);
+                    next_ts = segment_ts + (ts.duration as f64 * 1000.0) as i64;
                     let mut ts_entry = TsEntry {
                         url: ts.uri,
                         sequence,
                         _length: ts.duration as f64,
                         size: 0,
+                        ts: segment_ts,
                     };
                     let client = self.client.clone();
                     let ts_url = self.ts_url(&ts_entry.url).await?;
@@ -464,6 +823,7 @@ impl BiliRecorder {
                             }
                         }
                     }));
+                    timeline += &format!("{} {}\n", ts_entry.sequence, ts_entry.ts);
                     let mut entries = self.ts_entries.lock().await;
                     entries.push(ts_entry);
                     *self.last_sequence.write().await = sequence;
@@ -476,6 +836,17 @@ impl BiliRecorder {
                         log::error!("download ts failed: {:?}", e);
                     }
                 });
+                // keep fetch time of segments for archives
+                if !timeline.is_empty() {
+                    if let Err(e) = std::fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(format!("{}/{}", work_dir, TIMELINE_FILE))
+                        .and_then(|mut f| f.write_all(timeline.as_bytes()))
+                    {
+                        log::error!("Write timeline failed: {}", e);
+                    }
+                }
                 // currently we take every segement's length as 1.0s.
                 self.db
                     .update_record(
@@ -484,6 +855,11 @@ impl BiliRecorder {
                         *self.cache_size.read().await,
                     )
                     .await?;
+                // a sprite sheet is completed
+                let length = self.ts_entries.lock().await.len();
+                if length / trickplay::SHEET_SEGMENTS > last_length / trickplay::SHEET_SEGMENTS {
+                    self.update_trickplay(timestamp, false).await;
+                }
             }
             Err(_) => {
                 return Err(RecorderError::InvalidPlaylist);
@@ -505,9 +881,184 @@ impl BiliRecorder {
         log::info!("Restore {} entries from local file", entries.len());
     }
 
-    pub async fn clip(&self, ts: u64, d: f64, output_path: &str) -> Result<String, RecorderError> {
+    /// Output of clip, preset, layout and name template are validated before ffmpeg is spawned
+    async fn clip_output(&self, options: &ClipOptions) -> Result<ClipOutput, RecorderError> {
+        let preset = self.encoding_preset(options).await?;
+        if let Some(template) = &options.name_template {
+            naming::validate(template).map_err(|err| RecorderError::InvalidNameTemplate { err })?;
+        }
+        let loudnorm = match &options.loudnorm {
+            Some(loudnorm) if !options.format.is_animation() => {
+                loudnorm
+                    .validate()
+                    .map_err(|err| RecorderError::InvalidLoudnorm { err })?;
+                Some(loudnorm.clone())
+            }
+            _ => None,
+        };
+        let layout = match options.layout {
+            Some(id) => {
+                let row = self.db.get_layout(self.room_id, id).await.map_err(|e| {
+                    RecorderError::InvalidLayout {
+                        err: format!("layout {} of room {}: {}", id, self.room_id, e),
+                    }
+                })?;
+                let layout = VerticalLayout {
+                    width: row.width as u32,
+                    height: row.height as u32,
+                    regions: serde_json::from_str(&row.regions)
+                        .map_err(|e| RecorderError::InvalidLayout { err: e.to_string() })?,
+                    blur_background: row.blur_background,
+                };
+                layout
+                    .validate()
+                    .map_err(|err| RecorderError::InvalidLayout { err })?;
+                Some(layout)
+            }
+            None => None,
+        };
+        Ok(ClipOutput {
+            preset,
+            layout,
+            format: options.format,
+            animation: options.animation.clone().unwrap_or_default(),
+            loudnorm,
+            metadata: Vec::new(),
+        })
+    }
+
+    /// Encoding preset for clip
+    async fn encoding_preset(&self, options: &ClipOptions) -> Result<EncodingPreset, RecorderError> {
+        let config = self.config.read().await;
+        let name = options
+            .preset
+            .clone()
+            .or_else(|| config.room_presets.get(&self.room_id.to_string()).cloned());
+        let preset = match name {
+            Some(name) => config
+                .encoding_presets
+                .iter()
+                .find(|p| p.name == name)
+                .cloned()
+                .ok_or(RecorderError::PresetNotFound { name })?,
+            // "default" preset may be edited by user, built-in one is used only if it is removed
+            None => config
+                .encoding_presets
+                .iter()
+                .find(|p| p.name == EncodingPreset::default().name)
+                .cloned()
+                .unwrap_or_default(),
+        };
+        preset
+            .validate()
+            .map_err(|err| RecorderError::InvalidPreset { err })?;
+        Ok(preset)
+    }
+
+    /// Metadata of clip made from sources, title and record date come from the first source
+    async fn clip_metadata(&self, sources: Vec<ClipSource>, duration: f64) -> ClipMetadata {
+        let live_id = sources.first().map(|s| s.live_id).unwrap_or(0);
+        let room_title = self.room_info.read().await.room_title.clone();
+        let (title, recorded_at) = match self.db.get_record(self.room_id, live_id).await {
+            Ok(record) => (record.title, record.created_at),
+            Err(_) => (room_title, String::new()),
+        };
+        ClipMetadata {
+            title,
+            streamer: self.user_info.read().await.user_name.clone(),
+            room_id: self.room_id,
+            sources,
+            duration,
+            recorded_at,
+            created_at: Utc::now().to_rfc3339(),
+            tool: ClipMetadata::tool_version(),
+            loudness: None,
+        }
+    }
+
+    /// Output file for clip, named by template and never overwrites existing file
+    async fn clip_file_name(
+        &self,
+        options: &ClipOptions,
+        output_path: &str,
+        metadata: &ClipMetadata,
+        ext: &str,
+    ) -> Result<String, RecorderError> {
+        let (live_id, offset) = metadata
+            .sources
+            .first()
+            .map(|s| (s.live_id, s.start))
+            .unwrap_or((0, 0.0));
+        let is_live = *self.timestamp.read().await == live_id;
+        let template = match &options.name_template {
+            Some(template) => template.clone(),
+            None => {
+                let config = self.config.read().await;
+                if is_live {
+                    config.clip_name_template.clone()
+                } else {
+                    config.archive_name_template.clone()
+                }
+            }
+        };
+        let ctx = NameContext {
+            room_id: self.room_id,
+            streamer: metadata.streamer.clone(),
+            title: metadata.title.clone(),
+            live_id,
+            offset,
+            duration: metadata.duration,
+            date: Local::now(),
+        };
+        let name = naming::render(&template, &ctx)
+            .map_err(|err| RecorderError::InvalidNameTemplate { err })?;
+        std::fs::create_dir_all(output_path).expect("create clips folder failed");
+        Ok(naming::unique_path(
+            output_path,
+            &naming::sanitize(&name),
+            ext,
+        ))
+    }
+
+    /// Normalize loudness and write sidecar JSON of finished clip if enabled
+    async fn finish_clip(
+        &self,
+        file: String,
+        mut metadata: ClipMetadata,
+        output: &ClipOutput,
+    ) -> ClipResult {
+        if let Some(loudnorm) = &output.loudnorm {
+            match loudness::normalize(
+                &file,
+                loudnorm,
+                &output.preset.audio_codec_args(),
+                &metadata.ffmpeg_args(),
+            ) {
+                Ok(report) => metadata.loudness = Some(report),
+                Err(e) => log::error!("Loudness normalization failed: {}", e),
+            }
+        }
+        if self.config.read().await.clip_sidecar {
+            if let Err(e) = metadata.write_sidecar(&file) {
+                log::error!("Write clip metadata failed: {}", e);
+            }
+        }
+        ClipResult {
+            file,
+            live_id: metadata.sources.first().map(|s| s.live_id).unwrap_or(0),
+            loudness: metadata.loudness,
+        }
+    }
+
+    pub async fn clip(
+        &self,
+        ts: u64,
+        d: f64,
+        output_path: &str,
+        options: &ClipOptions,
+    ) -> Result<ClipResult, RecorderError> {
         let total_length = *self.ts_length.read().await;
-        self.clip_range(ts, total_length - d, total_length, output_path)
+        self.clip_range(ts, total_length - d, total_length, output_path, options)
             .await
     }
 
@@ -518,11 +1069,13 @@ impl BiliRecorder {
         x: f64,
         y: f64,
         output_path: &str,
-    ) -> Result<String, RecorderError> {
+        options: &ClipOptions,
+    ) -> Result<ClipResult, RecorderError> {
         if *self.timestamp.read().await == ts {
-            self.clip_live_range(x, y, output_path).await
+            self.clip_live_range(x, y, output_path, options).await
         } else {
-            self.clip_archive_range(ts, x, y, output_path).await
+            self.clip_archive_range(ts, x, y, output_path, options)
+                .await
         }
     }
 
@@ -532,8 +1085,11 @@ impl BiliRecorder {
         x: f64,
         y: f64,
         output_path: &str,
-    ) -> Result<String, RecorderError> {
+        options: &ClipOptions,
+    ) -> Result<ClipResult, RecorderError> {
         log::info!("create archive clip for range [{}, {}]", x, y);
+        let mut output = self.clip_output(options).await?;
+        output.check((y - x).abs())?;
         let work_dir = format!("{}/{}/{}", self.config.read().await.cache, self.room_id, ts);
         let entries = self.get_fs_entries(&work_dir).await;
         if entries.is_empty() {
@@ -560,30 +1116,375 @@ impl BiliRecorder {
             }
         }
 
+        let source = ClipSource {
+            live_id: ts,
+            start: x,
+            end: y,
+        };
+        let metadata = self.clip_metadata(vec![source], y - x).await;
+        let file_name = self
+            .clip_file_name(options, output_path, &metadata, output.extension())
+            .await?;
+        log::info!("{}", file_name);
+        output.metadata = metadata.ffmpeg_args();
+        concat_segments(&file_list, &file_name, &output)?;
+        Ok(self.finish_clip(file_name, metadata, &output).await)
+    }
+
+    /// start and end are wall-clock time in milliseconds, resolved by segment fetch time
+    pub async fn clip_time_range(
+        &self,
+        start: i64,
+        end: i64,
+        output_path: &str,
+        options: &ClipOptions,
+    ) -> Result<ClipResult, RecorderError> {
+        log::info!("create clip for time range [{}, {}]", start, end);
+        let output = self.clip_output(options).await?;
+        let (start, end) = if start > end { (end, start) } else { (start, end) };
+        let parts = self.resolve_time_range(start, end).await?;
+        self.clip_sessions(parts, output_path, output, options)
+            .await
+    }
+
+    /// x and y are relative to the first sequence of the timeline merged from sessions in live_ids
+    pub async fn clip_merged_range(
+        &self,
+        live_ids: &[u64],
+        x: f64,
+        y: f64,
+        output_path: &str,
+        options: &ClipOptions,
+    ) -> Result<ClipResult, RecorderError> {
+        log::info!("create merged clip of {:?} for range [{}, {}]", live_ids, x, y);
+        let output = self.clip_output(options).await?;
+        let (start, end) = if x > y { (y, x) } else { (x, y) };
+        let mut parts = Vec::new();
+        let mut offset = 0.0;
+        for live_id in live_ids {
+            let mut part = Vec::new();
+            for e in self.get_entries(*live_id).await {
+                if offset >= start && offset <= end {
+                    part.push(e);
+                }
+                offset += 1.0;
+            }
+            if !part.is_empty() {
+                parts.push((*live_id, part));
+            }
+        }
+        self.clip_sessions(parts, output_path, output, options)
+            .await
+    }
+
+    /// Clip segments from one or more sessions, every session is initialized with its own header
+    async fn clip_sessions(
+        &self,
+        parts: Vec<(u64, Vec<TsEntry>)>,
+        output_path: &str,
+        mut output: ClipOutput,
+        options: &ClipOptions,
+    ) -> Result<ClipResult, RecorderError> {
+        if parts.is_empty() {
+            return Err(RecorderError::EmptyCache);
+        }
+        let cache = self.config.read().await.cache.clone();
+        let length: usize = parts.iter().map(|(_, entries)| entries.len()).sum();
+        output.check(length as f64)?;
+        let mut sources = Vec::new();
+        for (live_id, entries) in parts.iter() {
+            // offset of the first segment in its own session
+            let offset = self
+                .get_entries(*live_id)
+                .await
+                .iter()
+                .position(|e| e.sequence == entries[0].sequence)
+                .unwrap_or(0) as f64;
+            sources.push(ClipSource {
+                live_id: *live_id,
+                start: offset,
+                end: offset + entries.len() as f64 - 1.0,
+            });
+        }
+        let metadata = self.clip_metadata(sources, length as f64).await;
+        let file_name = self
+            .clip_file_name(options, output_path, &metadata, output.extension())
+            .await?;
+        log::info!("{}", file_name);
+        if parts.len() == 1 {
+            let file_list = self.session_file_list(&cache, parts[0].0, &parts[0].1);
+            output.metadata = metadata.ffmpeg_args();
+            concat_segments(&file_list, &file_name, &output)?;
+            return Ok(self.finish_clip(file_name, metadata, &output).await);
+        }
+        // temp files are named after the clip, so clips made at the same time do not collide
+        let stem = std::path::Path::new(&file_name)
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default()
+            .to_string();
+        let mut part_files = Vec::new();
+        let result = self.join_sessions(
+            &parts,
+            &cache,
+            &stem,
+            &file_name,
+            &mut part_files,
+            &mut output,
+            &metadata,
+        );
+        for part_file in part_files {
+            if let Err(e) = std::fs::remove_file(&part_file) {
+                log::warn!("Remove clip part {} failed: {}", part_file, e);
+            }
+        }
+        result?;
+        Ok(self.finish_clip(file_name, metadata, &output).await)
+    }
+
+    /// Clip every session into a part, then join parts into file_name. Created temp files are
+    /// pushed into part_files even if a step failed, so the caller can clean them up
+    #[allow(clippy::too_many_arguments)]
+    fn join_sessions(
+        &self,
+        parts: &[(u64, Vec<TsEntry>)],
+        cache: &str,
+        stem: &str,
+        file_name: &str,
+        part_files: &mut Vec<String>,
+        output: &mut ClipOutput,
+        metadata: &ClipMetadata,
+    ) -> Result<(), RecorderError> {
+        for (index, (live_id, entries)) in parts.iter().enumerate() {
+            let file_list = self.session_file_list(cache, *live_id, entries);
+            let part_file = std::env::temp_dir()
+                .join(format!("{}_part{}.mp4", stem, index))
+                .to_str()
+                .unwrap()
+                .to_string();
+            part_files.push(part_file.clone());
+            concat_segments(&file_list, &part_file, &output.intermediate())?;
+        }
+        if output.format == ClipFormat::Mp4 {
+            return concat_files(part_files, file_name, &metadata.ffmpeg_args());
+        }
+        // parts are joined in mp4 first, then converted as a whole
+        let joined = std::env::temp_dir()
+            .join(format!("{}_joined.mp4", stem))
+            .to_str()
+            .unwrap()
+            .to_string();
+        let files = part_files.clone();
+        part_files.push(joined.clone());
+        concat_files(&files, &joined, &[])?;
+        output.metadata = metadata.ffmpeg_args();
+        convert_file(&joined, file_name, &output.conversion())
+    }
+
+    /// Concat list of header and segments in session live_id
+    fn session_file_list(&self, cache: &str, live_id: u64, entries: &[TsEntry]) -> String {
+        let work_dir = format!("{}/{}/{}", cache, self.room_id, live_id);
+        let mut file_list = String::new();
+        // header fist
+        file_list += &format!("{}/h{}.m4s", work_dir, live_id);
+        file_list += "|";
+        for e in entries.iter() {
+            let file_name = e.url.split('/').last().unwrap();
+            file_list += &format!("{}/{}", work_dir, file_name);
+            file_list += "|";
+        }
+        file_list
+    }
+
+    /// Clip ranges in order into one file, returns the file and its length in seconds
+    pub async fn clip_compilation(
+        &self,
+        ranges: &[ClipRange],
+        compilation: &CompilationOptions,
+        output_path: &str,
+        options: &ClipOptions,
+    ) -> Result<(ClipResult, f64), RecorderError> {
+        log::info!("create compilation of {} ranges", ranges.len());
+        let mut output = self.clip_output(options).await?;
+        if ranges.is_empty() {
+            return Err(RecorderError::EmptyCache);
+        }
+        output.check(ranges.iter().map(|r| (r.end - r.start).abs()).sum())?;
+        let intermediate = output.intermediate();
+        let (cache, font) = {
+            let config = self.config.read().await;
+            (config.cache.clone(), title_font(&config.title_font))
+        };
+        let mut compilation = compilation.clone();
+        if compilation.title_card > 0.0 && font.is_none() {
+            log::warn!("No font for title cards is found, title cards are skipped");
+            compilation.title_card = 0.0;
+        }
+        let now = Utc::now();
+        let mut parts = Vec::new();
+        for (index, range) in ranges.iter().enumerate() {
+            let (start, end) = if range.start > range.end {
+                (range.end, range.start)
+            } else {
+                (range.start, range.end)
+            };
+            let entries: Vec<TsEntry> = self
+                .get_entries(range.live_id)
+                .await
+                .into_iter()
+                .enumerate()
+                .filter(|(offset, _)| *offset as f64 >= start && *offset as f64 <= end)
+                .map(|(_, e)| e)
+                .collect();
+            if entries.is_empty() {
+                return Err(RecorderError::InvalidOffset { offset: start });
+            }
+            let file_list = self.session_file_list(&cache, range.live_id, &entries);
+            let part_file = std::env::temp_dir()
+                .join(format!(
+                    "[{}]compilation_{}_part{}.mp4",
+                    self.room_id,
+                    now.timestamp(),
+                    index
+                ))
+                .to_str()
+                .unwrap()
+                .to_string();
+            if let Err(e) = concat_segments(&file_list, &part_file, &intermediate) {
+                remove_parts(&parts);
+                let _ = std::fs::remove_file(&part_file);
+                return Err(e);
+            }
+            let duration = probe_duration(&part_file).unwrap_or(entries.len() as f64);
+            parts.push(CompilationPart {
+                file: part_file,
+                duration,
+                title: range.title.clone(),
+            });
+        }
+        let sources = ranges
+            .iter()
+            .map(|r| ClipSource {
+                live_id: r.live_id,
+                start: r.start.min(r.end),
+                end: r.start.max(r.end),
+            })
+            .collect();
+        let mut metadata = self.clip_metadata(sources, 0.0).await;
+        output.metadata = metadata.ffmpeg_args();
+        // other formats are converted from compiled mp4
+        let compiled = if output.format == ClipFormat::Mp4 {
+            output.clone()
+        } else {
+            intermediate
+        };
         std::fs::create_dir_all(output_path).expect("create clips folder failed");
-        let file_name = format!(
-            "{}/[{}]{}_{}_{:.1}.mp4",
+        let mut file_name = format!(
+            "{}/[{}]compilation_{}.mp4",
             output_path,
             self.room_id,
-            ts,
-            Utc::now().format("%m%d%H%M%S"),
-            y - x
+            now.timestamp(),
         );
+        let length = if compilation.transition <= 0.0 && compilation.title_card <= 0.0 {
+            let files: Vec<String> = parts.iter().map(|p| p.file.clone()).collect();
+            concat_files(&files, &file_name, &compiled.metadata)
+                .map(|_| parts.iter().map(|p| p.duration).sum())
+        } else {
+            compile_parts(&parts, &compilation, font.as_deref(), &compiled, &file_name)
+        };
+        remove_parts(&parts);
+        let length = length?;
+        // length is only known after compiling
+        metadata.duration = length;
+        let final_name = self
+            .clip_file_name(options, output_path, &metadata, output.extension())
+            .await?;
+        if output.format != ClipFormat::Mp4 {
+            let result = convert_file(&file_name, &final_name, &output.conversion());
+            if let Err(e) = std::fs::remove_file(&file_name) {
+                log::warn!("Remove compiled file {} failed: {}", file_name, e);
+            }
+            result?;
+            file_name = final_name;
+        } else if std::fs::rename(&file_name, &final_name).is_ok() {
+            file_name = final_name;
+        }
         log::info!("{}", file_name);
-        let args = format!("-i concat:{} -c:v libx264 -c:a aac", file_list);
-        FfmpegCommand::new()
-            .args(args.split(' '))
-            .output(file_name.clone())
-            .spawn()
-            .unwrap()
+        Ok((self.finish_clip(file_name, metadata, &output).await, length))
+    }
+
+    /// Find sessions that cover wall-clock time range, and segments of every session inside the range
+    async fn resolve_time_range(
+        &self,
+        start: i64,
+        end: i64,
+    ) -> Result<Vec<(u64, Vec<TsEntry>)>, RecorderError> {
+        let sessions = self.get_timed_sessions().await?;
+        // both ends must be covered by segments, gaps inside the range are skipped
+        if !sessions
             .iter()
-            .unwrap()
-            .for_each(|e| match e {
-                FfmpegEvent::Log(LogLevel::Error, e) => log::error!("Error: {}", e),
-                FfmpegEvent::Progress(p) => log::info!("Progress: {}", p.time),
-                _ => {}
-            });
-        Ok(file_name)
+            .any(|(_, entries)| locate_time(entries, start).is_some())
+        {
+            return Err(RecorderError::TimeInGap { time: start });
+        }
+        if !sessions
+            .iter()
+            .any(|(_, entries)| locate_time(entries, end).is_some())
+        {
+            return Err(RecorderError::TimeInGap { time: end });
+        }
+        let mut parts = Vec::new();
+        for (record, entries) in sessions {
+            let from = entries.partition_point(|e| e.ts + 1000 <= start);
+            let to = entries.partition_point(|e| e.ts <= end);
+            if from < to {
+                parts.push((record.live_id, entries[from..to].to_vec()));
+            }
+        }
+        Ok(parts)
+    }
+
+    /// Sessions with recorded segment time, ordered by the time of first segment
+    async fn get_timed_sessions(&self) -> Result<Vec<(RecordRow, Vec<TsEntry>)>, RecorderError> {
+        let mut sessions = Vec::new();
+        for record in self.db.get_records(self.room_id).await? {
+            let entries = self.get_entries(record.live_id).await;
+            if entries.is_empty() || entries.iter().any(|e| e.ts == 0) {
+                continue;
+            }
+            sessions.push((record, entries));
+        }
+        sessions.sort_by(|a, b| a.1[0].ts.cmp(&b.1[0].ts));
+        Ok(sessions)
+    }
+
+    /// Adjacent sessions are merged into one timeline, if the gap between them is within MERGE_THRESHOLD_MS.
+    /// Sessions recorded without segment time are not included.
+    pub async fn get_merged_archives(&self) -> Result<Vec<MergedSession>, RecorderError> {
+        let mut merged: Vec<MergedSession> = Vec::new();
+        for (record, entries) in self.get_timed_sessions().await? {
+            let span = SessionSpan {
+                live_id: record.live_id,
+                title: record.title,
+                start: entries[0].ts,
+                end: entries.last().unwrap().ts + 1000,
+                length: entries.len() as i64,
+            };
+            match merged.last_mut() {
+                Some(last) if span.start - last.end <= MERGE_THRESHOLD_MS => {
+                    last.end = last.end.max(span.end);
+                    last.length += span.length;
+                    last.sessions.push(span);
+                }
+                _ => merged.push(MergedSession {
+                    start: span.start,
+                    end: span.end,
+                    length: span.length,
+                    sessions: vec![span],
+                }),
+            }
+        }
+        Ok(merged)
     }
 
     pub async fn clip_live_range(
@@ -591,8 +1492,10 @@ impl BiliRecorder {
         x: f64,
         y: f64,
         output_path: &str,
-    ) -> Result<String, RecorderError> {
+        options: &ClipOptions,
+    ) -> Result<ClipResult, RecorderError> {
         log::info!("create live clip for range [{}, {}]", x, y);
+        let mut output = self.clip_output(options).await?;
         let mut to_combine = Vec::new();
         let header_copy = self.header.read().await.clone();
         let entry_copy = self.ts_entries.lock().await.clone();
@@ -604,6 +1507,7 @@ impl BiliRecorder {
         if start > end {
             std::mem::swap(&mut start, &mut end);
         }
+        output.check(end - start)?;
         let mut offset = 0.0;
         for e in entry_copy.iter() {
             if offset < start {
@@ -632,32 +1536,27 @@ impl BiliRecorder {
             file_list += &file_path;
             file_list += "|";
         }
-        let title = self.room_info.read().await.room_title.clone();
-        let title: String = title.chars().take(5).collect();
-        std::fs::create_dir_all(output_path).expect("create clips folder failed");
-        let file_name = format!(
-            "{}/[{}]{}_{}_{:.1}.mp4",
-            output_path,
-            self.room_id,
-            title,
-            Utc::now().format("%m%d%H%M%S"),
-            end - start
-        );
+        let source = ClipSource {
+            live_id: timestamp,
+            start,
+            end,
+        };
+        let metadata = self.clip_metadata(vec![source], end - start).await;
+        let file_name = self
+            .clip_file_name(options, output_path, &metadata, output.extension())
+            .await?;
         log::info!("{}", file_name);
-        let args = format!("-i concat:{} -c:v libx264 -c:a aac", file_list);
-        FfmpegCommand::new()
-            .args(args.split(' '))
-            .output(file_name.clone())
-            .spawn()
-            .unwrap()
-            .iter()
-            .unwrap()
-            .for_each(|e| match e {
-                FfmpegEvent::Log(LogLevel::Error, e) => log::error!("Error: {}", e),
-                FfmpegEvent::Progress(p) => log::info!("Progress: {}", p.time),
-                _ => {}
-            });
-        Ok(file_name)
+        output.metadata = metadata.ffmpeg_args();
+        concat_segments(&file_list, &file_name, &output)?;
+        Ok(self.finish_clip(file_name, metadata, &output).await)
+    }
+
+    /// Path of trickplay WebVTT on HLS server, None if no sprite is generated yet
+    pub async fn get_trickplay(&self, live_id: u64) -> Option<String> {
+        let work_dir = format!("{}/{}/{}", self.config.read().await.cache, self.room_id, live_id);
+        std::path::Path::new(&format!("{}/{}", work_dir, trickplay::VTT_FILE))
+            .exists()
+            .then(|| format!("/{}/{}/{}", self.room_id, live_id, trickplay::VTT_FILE))
     }
 
     /// timestamp is the id of live stream
@@ -684,11 +1583,15 @@ impl BiliRecorder {
         if entries.is_empty() {
             return m3u8_content;
         }
+        // archives recorded before trickplay was introduced get sprites on first view
+        self.update_trickplay(timestamp, true).await;
         let mut last_sequence = entries.first().unwrap().sequence;
+        m3u8_content += &program_date_time(&entries[0]);
         for e in entries {
             let current_seq = e.sequence;
             if current_seq - last_sequence > 1 {
-                m3u8_content += "#EXT-X-DISCONTINUITY\n"
+                m3u8_content += "#EXT-X-DISCONTINUITY\n";
+                m3u8_content += &program_date_time(&e);
             }
             last_sequence = current_seq;
             m3u8_content += "#EXTINF:1,\n";
@@ -705,6 +1608,19 @@ impl BiliRecorder {
         if direntry.is_err() {
             return ret;
         }
+        // sequence -> fetch time, archives recorded before timeline was introduced have none
+        let mut timeline = HashMap::new();
+        if let Ok(content) = fs::read_to_string(format!("{}/{}", path, TIMELINE_FILE)).await {
+            for line in content.lines() {
+                let mut parts = line.split(' ');
+                if let (Some(Ok(sequence)), Some(Ok(ts))) = (
+                    parts.next().map(|x| x.parse::<u64>()),
+                    parts.next().map(|x| x.parse::<i64>()),
+                ) {
+                    timeline.insert(sequence, ts);
+                }
+            }
+        }
         let mut direntry = direntry.unwrap();
         while let Some(e) = direntry.next().await {
             if e.is_err() {
@@ -723,11 +1639,17 @@ impl BiliRecorder {
             if file_name.starts_with("h") {
                 continue;
             }
+            // skip files that are not segments, like timeline
+            let sequence = match file_name.split('.').next().unwrap().parse::<u64>() {
+                Ok(sequence) => sequence,
+                Err(_) => continue,
+            };
             ret.push(TsEntry {
                 url: file_name.clone(),
-                sequence: file_name.split('.').next().unwrap().parse().unwrap(),
+                sequence,
                 _length: 1.0,
                 size: e.metadata().await.unwrap().len(),
+                ts: timeline.get(&sequence).copied().unwrap_or(0),
             });
         }
         ret.sort_by(|a, b| a.sequence.cmp(&b.sequence));
@@ -758,10 +1680,12 @@ impl BiliRecorder {
             return m3u8_content;
         }
         let mut last_sequence = entries.first().unwrap().sequence;
+        m3u8_content += &program_date_time(&entries[0]);
         for entry in entries.iter() {
             if entry.sequence - last_sequence > 1 {
                 // discontinuity happens
-                m3u8_content += "#EXT-X-DISCONTINUITY\n"
+                m3u8_content += "#EXT-X-DISCONTINUITY\n";
+                m3u8_content += &program_date_time(entry);
             }
             last_sequence = entry.sequence;
             m3u8_content += "#EXTINF:1,\n";
@@ -776,3 +1700,279 @@ impl BiliRecorder {
         m3u8_content
     }
 }
+
+/// EXT-X-PROGRAM-DATE-TIME tag for the segment, empty if its fetch time is unknown
+fn program_date_time(entry: &TsEntry) -> String {
+    match Utc.timestamp_millis_opt(entry.ts).single() {
+        Some(time) if entry.ts > 0 => format!(
+            "#EXT-X-PROGRAM-DATE-TIME:{}\n",
+            time.to_rfc3339_opts(SecondsFormat::Millis, true)
+        ),
+        _ => String::new(),
+    }
+}
+
+/// Fetch time difference larger than this between neighbouring segments is taken as a gap
+const GAP_THRESHOLD_MS: i64 = 5000;
+
+/// Index of the segment that covers time, None if time is outside entries or inside a gap
+fn locate_time(entries: &[TsEntry], time: i64) -> Option<usize> {
+    let index = entries.partition_point(|e| e.ts <= time);
+    if index == 0 {
+        return None;
+    }
+    let entry = &entries[index - 1];
+    // every segment is taken as 1.0s
+    if time - entry.ts < 1000 {
+        return Some(index - 1);
+    }
+    match entries.get(index) {
+        Some(next)
+            if next.sequence - entry.sequence == 1 && next.ts - entry.ts <= GAP_THRESHOLD_MS =>
+        {
+            Some(index - 1)
+        }
+        _ => None,
+    }
+}
+
+/// Duration of media file in seconds, parsed from ffmpeg output
+fn probe_duration(file: &str) -> Option<f64> {
+    let mut duration = None;
+    FfmpegCommand::new()
+        .input(file)
+        .spawn()
+        .ok()?
+        .iter()
+        .ok()?
+        .for_each(|e| {
+            if let FfmpegEvent::ParsedDuration(d) = e {
+                duration = Some(d.duration);
+            }
+        });
+    duration
+}
+
+fn remove_parts(parts: &[CompilationPart]) {
+    for part in parts {
+        if let Err(e) = std::fs::remove_file(&part.file) {
+            log::warn!("Remove clip part {} failed: {}", part.file, e);
+        }
+    }
+}
+
+/// Text for drawtext, quotes are replaced as filter graph is single quoted
+fn escape_drawtext(text: &str) -> String {
+    text.replace('\\', "").replace('\'', "\u{2019}")
+}
+
+/// Font file for title cards, configured one is preferred over platform fonts
+fn title_font(configured: &str) -> Option<String> {
+    if !configured.is_empty() {
+        if std::path::Path::new(configured).exists() {
+            return Some(configured.to_string());
+        }
+        log::warn!("Title font {} not found, platform font is used", configured);
+    }
+    TITLE_FONTS
+        .iter()
+        .find(|f| std::path::Path::new(f).exists())
+        .map(|f| f.to_string())
+}
+
+/// Font path for drawtext, colon of windows drive is escaped as the value is parsed again after
+/// filter graph removes quotes
+fn escape_fontfile(path: &str) -> String {
+    path.replace('\\', "/").replace(':', "\\:")
+}
+
+/// Re-encode parts with title cards and crossfade, returns length of the output in seconds.
+/// font is required if options has title cards
+fn compile_parts(
+    parts: &[CompilationPart],
+    options: &CompilationOptions,
+    font: Option<&str>,
+    output: &ClipOutput,
+    file_name: &str,
+) -> Result<f64, RecorderError> {
+    let preset = &output.preset;
+    // canvas follows layout or preset, missing side is derived in 16:9
+    let (width, height) = match (&output.layout, preset.width, preset.height) {
+        (Some(layout), _, _) => (layout.width, layout.height),
+        (None, Some(w), Some(h)) => (w, h),
+        (None, Some(w), None) => (w, w * 9 / 16 / 2 * 2),
+        (None, None, Some(h)) => (h * 16 / 9 / 2 * 2, h),
+        (None, None, None) => (COMPILATION_WIDTH, COMPILATION_HEIGHT),
+    };
+    let fps = preset.fps.unwrap_or(COMPILATION_FPS);
+    let canvas = format!(
+        "scale={w}:{h}:force_original_aspect_ratio=decrease,pad={w}:{h}:(ow-iw)/2:(oh-ih)/2,setsar=1,fps={fps},format=yuv420p",
+        w = width,
+        h = height,
+        fps = fps
+    );
+    let audio = "aresample=48000,aformat=sample_fmts=fltp:channel_layouts=stereo";
+    let mut filters = Vec::new();
+    // (video label, audio label, duration)
+    let mut segments = Vec::new();
+    for (index, part) in parts.iter().enumerate() {
+        if let Some(font) = font.filter(|_| options.title_card > 0.0 && !part.title.is_empty()) {
+            filters.push(format!(
+                "color=c=black:s={}x{}:r={}:d={},drawtext=fontfile='{}':text='{}':expansion=none:fontcolor=white:fontsize=64:x=(w-text_w)/2:y=(h-text_h)/2,{}[tv{}]",
+                width,
+                height,
+                fps,
+                options.title_card,
+                escape_fontfile(font),
+                escape_drawtext(&part.title),
+                canvas,
+                index
+            ));
+            filters.push(format!(
+                "anullsrc=r=48000:cl=stereo,atrim=duration={},{}[ta{}]",
+                options.title_card, audio, index
+            ));
+            segments.push((
+                format!("tv{}", index),
+                format!("ta{}", index),
+                options.title_card,
+            ));
+        }
+        filters.push(format!("[{}:v]{}[v{}]", index, canvas, index));
+        filters.push(format!("[{}:a]{}[a{}]", index, audio, index));
+        segments.push((format!("v{}", index), format!("a{}", index), part.duration));
+    }
+    let length = if options.transition <= 0.0 {
+        let inputs: String = segments
+            .iter()
+            .map(|(v, a, _)| format!("[{}][{}]", v, a))
+            .collect();
+        filters.push(format!(
+            "{}concat=n={}:v=1:a=1[vout][aout]",
+            inputs,
+            segments.len()
+        ));
+        segments.iter().map(|(_, _, d)| d).sum()
+    } else {
+        // crossfade can not be longer than any segment
+        let shortest = segments
+            .iter()
+            .map(|(_, _, d)| *d)
+            .fold(f64::MAX, f64::min);
+        let transition = options.transition.min(shortest / 2.0);
+        let (mut video, mut audio, mut length) = segments[0].clone();
+        for (index, (v, a, d)) in segments.iter().enumerate().skip(1) {
+            filters.push(format!(
+                "[{}][{}]xfade=transition=fade:duration={}:offset={}[xv{}]",
+                video,
+                v,
+                transition,
+                length - transition,
+                index
+            ));
+            filters.push(format!(
+                "[{}][{}]acrossfade=d={}[xa{}]",
+                audio, a, transition, index
+            ));
+            video = format!("xv{}", index);
+            audio = format!("xa{}", index);
+            length += d - transition;
+        }
+        filters.push(format!("[{}]null[vout]", video));
+        filters.push(format!("[{}]anull[aout]", audio));
+        length
+    };
+    let mut command = FfmpegCommand::new();
+    for part in parts {
+        command.input(&part.file);
+    }
+    command
+        .args(["-filter_complex", &filters.join(";")])
+        .args(["-map", "[vout]", "-map", "[aout]"])
+        .args(preset.codec_args())
+        .args(output.metadata.clone())
+        .output(file_name);
+    run_ffmpeg(&mut command)?;
+    Ok(length)
+}
+
+/// Wait for ffmpeg to exit, errors in its log are collected into the returned error
+fn run_ffmpeg(command: &mut FfmpegCommand) -> Result<(), RecorderError> {
+    let mut child = command.spawn().map_err(|e| RecorderError::FfmpegFailed {
+        err: format!("spawn ffmpeg failed: {}", e),
+    })?;
+    let mut errors = Vec::new();
+    child
+        .iter()
+        .map_err(|e| RecorderError::FfmpegFailed { err: e.to_string() })?
+        .for_each(|e| match e {
+            FfmpegEvent::Log(LogLevel::Error, e) => {
+                log::error!("Error: {}", e);
+                errors.push(e);
+            }
+            FfmpegEvent::Progress(p) => log::info!("Progress: {}", p.time),
+            _ => {}
+        });
+    let status = child
+        .wait()
+        .map_err(|e| RecorderError::FfmpegFailed { err: e.to_string() })?;
+    if status.success() {
+        return Ok(());
+    }
+    Err(RecorderError::FfmpegFailed {
+        err: errors.last().cloned().unwrap_or_else(|| status.to_string()),
+    })
+}
+
+/// Concat clip parts into file_name without re-encoding
+fn concat_files(
+    files: &[String],
+    file_name: &str,
+    metadata: &[String],
+) -> Result<(), RecorderError> {
+    let list_file = format!("{}.txt", file_name);
+    let list: String = files
+        .iter()
+        .map(|f| format!("file '{}'\n", f.replace('\'', "'\\''")))
+        .collect();
+    if let Err(e) = std::fs::write(&list_file, list) {
+        return Err(RecorderError::FfmpegFailed {
+            err: format!("write concat list failed: {}", e),
+        });
+    }
+    let result = run_ffmpeg(
+        FfmpegCommand::new()
+            .args(["-f", "concat", "-safe", "0", "-i", &list_file])
+            .args(["-c", "copy"])
+            .args(metadata)
+            .output(file_name),
+    );
+    if let Err(e) = std::fs::remove_file(&list_file) {
+        log::warn!("Remove concat list failed: {}", e);
+    }
+    result
+}
+
+/// Convert intermediate mp4 into output format
+fn convert_file(input: &str, file_name: &str, output: &ClipOutput) -> Result<(), RecorderError> {
+    run_ffmpeg(
+        FfmpegCommand::new()
+            .input(input)
+            .args(output.args())
+            .output(file_name),
+    )
+}
+
+/// Concat segments in file_list, which is separated by '|', into file_name
+fn concat_segments(
+    file_list: &str,
+    file_name: &str,
+    output: &ClipOutput,
+) -> Result<(), RecorderError> {
+    run_ffmpeg(
+        FfmpegCommand::new()
+            .args(["-i", &format!("concat:{}", file_list)])
+            .args(output.args())
+            .output(file_name),
+    )
+}
@@ -1,29 +1,47 @@
 pub mod errors;
 pub mod profile;
 pub mod response;
+pub mod scheduler;
+#[cfg(test)]
+mod tests;
+pub mod upload;
 use crate::db::AccountRow;
 
 use super::StreamType;
+use errors::ApiErrorKind;
 use errors::BiliClientError;
+use futures::StreamExt;
 use pct_str::PctString;
 use pct_str::URIReserved;
 use profile::Profile;
 use regex::Regex;
 use reqwest::Client;
+use reqwest::StatusCode;
 use response::GeneralResponse;
 use response::PostVideoMetaResponse;
 use response::PreuploadResponse;
 use response::VideoSubmitData;
+use scheduler::RequestScheduler;
 use serde::Deserialize;
 use serde::Serialize;
 use serde_json::json;
 use serde_json::Value;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::io::SeekFrom;
 use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 use std::time::SystemTime;
 use tokio::fs::File;
 use tokio::io::AsyncReadExt;
+use tokio::io::AsyncSeekExt;
+use tokio::sync::mpsc;
 use tokio::sync::RwLock;
 use tokio::time::Instant;
+use upload::UploadOptions;
+use upload::UploadState;
+use upload::UploadedPart;
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -162,6 +180,19 @@ pub struct BiliClient {
     client: Client,
     headers: reqwest::header::HeaderMap,
     extra: RwLock<String>,
+    scheduler: Arc<RequestScheduler>,
+    wbi_key: Arc<RwLock<Option<WbiKey>>>,
+    preupload_url: String,
+}
+
+const PREUPLOAD_URL: &str = "https://member.bilibili.com/preupload";
+
+/// Keys of WBI signature are rotated daily
+const WBI_KEY_EXPIRE: Duration = Duration::from_secs(60 * 60);
+
+struct WbiKey {
+    key: String,
+    fetched_at: Instant,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -196,6 +227,44 @@ pub struct QrStatus {
     pub cookies: String,
 }
 
+/// Review state of a submitted archive
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArchiveState {
+    /// 0 is open, negative values are reviewing, rejected, locked and so on
+    pub state: i64,
+    pub state_desc: String,
+    pub reject_reason: String,
+}
+
+impl ArchiveState {
+    /// Waiting for review, recorded on submission before the first poll
+    pub const REVIEWING: i64 = -1;
+
+    pub fn is_passed(state: i64) -> bool {
+        // 1 is open with orange pass
+        state == 0 || state == 1
+    }
+
+    /// Rejected, locked, failed or deleted, state will not change by itself
+    pub fn is_rejected(state: i64) -> bool {
+        matches!(state, -2 | -3 | -4 | -5 | -12 | -16 | -100)
+    }
+
+    pub fn is_final(state: i64) -> bool {
+        Self::is_passed(state) || Self::is_rejected(state)
+    }
+}
+
+/// Url of uploaded file on upos, endpoint of preupload is protocol-relative like `//upos-cs-upcdnbda2.bilivideo.com`
+fn upos_url(preupload: &PreuploadResponse) -> String {
+    let endpoint = if preupload.endpoint.starts_with("//") {
+        format!("https:{}", preupload.endpoint)
+    } else {
+        preupload.endpoint.clone()
+    };
+    format!("{}{}", endpoint, preupload.upos_uri.replace("upos:/", ""))
+}
+
 impl BiliClient {
     pub fn new() -> Result<BiliClient, BiliClientError> {
         let mut headers = reqwest::header::HeaderMap::new();
@@ -209,18 +278,99 @@ impl BiliClient {
                 client,
                 headers,
                 extra: RwLock::new("".into()),
+                scheduler: Arc::new(RequestScheduler::default()),
+                wbi_key: Arc::new(RwLock::new(None)),
+                preupload_url: PREUPLOAD_URL.to_string(),
             })
         } else {
             Err(BiliClientError::InitClientError)
         }
     }
 
+    /// Client sharing connections, request scheduler and WBI key, with its own stream url parameters
+    pub fn fork(&self) -> BiliClient {
+        BiliClient {
+            client: self.client.clone(),
+            headers: self.headers.clone(),
+            extra: RwLock::new("".into()),
+            scheduler: self.scheduler.clone(),
+            wbi_key: self.wbi_key.clone(),
+            preupload_url: self.preupload_url.clone(),
+        }
+    }
+
+    /// Client uploading to a mock server
+    #[cfg(test)]
+    pub fn with_preupload_url(mut self, url: &str) -> BiliClient {
+        self.preupload_url = url.to_string();
+        self
+    }
+
+    /// Send API request after the scheduler gives it a slot
+    async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, BiliClientError> {
+        let request = request.build()?;
+        let endpoint = scheduler::endpoint(request.url());
+        let _permit = self.scheduler.acquire(&endpoint).await;
+        let response = self.client.execute(request).await?;
+        let status = response.status().as_u16();
+        if status == 412 || status == 429 {
+            self.scheduler
+                .report(&endpoint, Some(ApiErrorKind::from_response(status, 0)))
+                .await;
+        }
+        Ok(response)
+    }
+
+    /// JSON of API response, non-zero code and non-JSON body are errors carrying endpoint, status, code and message
+    async fn parse_response(&self, response: reqwest::Response) -> Result<Value, BiliClientError> {
+        let endpoint = scheduler::endpoint(response.url());
+        let status = response.status().as_u16();
+        let text = response.text().await?;
+        let res: Value = match serde_json::from_str(&text) {
+            Ok(res) => res,
+            Err(_) => {
+                return Err(BiliClientError::ApiError {
+                    endpoint,
+                    status,
+                    code: 0,
+                    message: text.chars().take(200).collect(),
+                })
+            }
+        };
+        let code = res["code"]
+            .as_i64()
+            .ok_or(BiliClientError::InvalidResponse)?;
+        if code != 0 || !(200..300).contains(&status) {
+            let err = BiliClientError::ApiError {
+                endpoint: endpoint.clone(),
+                status,
+                code,
+                message: res["message"]
+                    .as_str()
+                    .or(res["msg"].as_str())
+                    .unwrap_or("")
+                    .to_string(),
+            };
+            log::error!("{}", err);
+            // blocked HTTP status is reported on send
+            if status != 412 && status != 429 {
+                self.scheduler.report(&endpoint, err.kind()).await;
+            }
+            return Err(err);
+        }
+        self.scheduler.report(&endpoint, None).await;
+        Ok(res)
+    }
+
     pub async fn fetch_webid(&self, account: &AccountRow) -> Result<String, BiliClientError> {
         // get webid from html content
         // webid is in script tag <script id="__RENDER_DATA__" type="application/json">
         // https://space.bilibili.com/{user_id}
         let url = format!("https://space.bilibili.com/{}", account.uid);
-        let res = self.client.get(&url).send().await?;
+        let res = self.send(self.client.get(&url)).await?;
         let content = res.text().await?;
         let re =
             Regex::new(r#"<script id="__RENDER_DATA__" type="application/json">(.+?)</script>"#)
@@ -238,14 +388,12 @@ impl BiliClient {
     }
 
     pub async fn get_qr(&self) -> Result<QrInfo, BiliClientError> {
-        let res: serde_json::Value = self
+        let request = self
             .client
             .get("https://passport.bilibili.com/x/passport-login/web/qrcode/generate")
-            .headers(self.headers.clone())
-            .send()
-            .await?
-            .json()
-            .await?;
+            .headers(self.headers.clone());
+        let res = self.send(request).await?;
+        let res = self.parse_response(res).await?;
         Ok(QrInfo {
             oauth_key: res["data"]["qrcode_key"]
                 .as_str()
@@ -259,17 +407,16 @@ impl BiliClient {
     }
 
     pub async fn get_qr_status(&self, qrcode_key: &str) -> Result<QrStatus, BiliClientError> {
-        let res: serde_json::Value = self
+        let request = self
             .client
             .get(format!(
                 "https://passport.bilibili.com/x/passport-login/web/qrcode/poll?qrcode_key={}",
                 qrcode_key
             ))
-            .headers(self.headers.clone())
-            .send()
-            .await?
-            .json()
-            .await?;
+            .headers(self.headers.clone());
+        let res = self.send(request).await?;
+        // code of response is 0 while waiting, status of login is in data.code
+        let res = self.parse_response(res).await?;
         let code: u8 = res["data"]["code"].as_u64().unwrap_or(400) as u8;
         let mut cookies: String = "".to_string();
         if code == 0 {
@@ -288,14 +435,13 @@ impl BiliClient {
         let mut headers = self.headers.clone();
         headers.insert("cookie", account.cookies.parse().unwrap());
         let params = [("csrf", account.csrf.clone())];
-        let _ = self
+        let request = self
             .client
             .post(url)
             .headers(headers)
             .header("Content-Type", "application/x-www-form-urlencoded")
-            .form(&params)
-            .send()
-            .await?;
+            .form(&params);
+        let _ = self.send(request).await?;
         Ok(())
     }
 
@@ -315,24 +461,15 @@ impl BiliClient {
         let params = self.get_sign(params).await?;
         let mut headers = self.headers.clone();
         headers.insert("cookie", account.cookies.parse().unwrap());
-        let res: serde_json::Value = self
+        let request = self
             .client
             .get(format!(
                 "https://api.bilibili.com/x/space/wbi/acc/info?{}",
                 params
             ))
-            .headers(headers)
-            .send()
-            .await?
-            .json()
-            .await?;
-        if res["code"].as_i64().unwrap_or(-1) != 0 {
-            log::error!(
-                "Get user info failed {}",
-                res["code"].as_i64().unwrap_or(-1)
-            );
-            return Err(BiliClientError::InvalidCode);
-        }
+            .headers(headers);
+        let res = self.send(request).await?;
+        let res = self.parse_response(res).await?;
         Ok(UserInfo {
             user_id,
             user_name: res["data"]["name"].as_str().unwrap_or("").to_string(),
@@ -348,21 +485,15 @@ impl BiliClient {
     ) -> Result<RoomInfo, BiliClientError> {
         let mut headers = self.headers.clone();
         headers.insert("cookie", account.cookies.parse().unwrap());
-        let res: serde_json::Value = self
+        let request = self
             .client
             .get(format!(
                 "https://api.live.bilibili.com/room/v1/Room/get_info?room_id={}",
                 room_id
             ))
-            .headers(headers)
-            .send()
-            .await?
-            .json()
-            .await?;
-        let code = res["code"].as_u64().ok_or(BiliClientError::InvalidValue)?;
-        if code != 0 {
-            return Err(BiliClientError::InvalidCode);
-        }
+            .headers(headers);
+        let res = self.send(request).await?;
+        let res = self.parse_response(res).await?;
 
         let room_id = res["data"]["room_id"]
             .as_u64()
@@ -395,6 +526,44 @@ impl BiliClient {
         })
     }
 
+    /// Room info of rooms by real room ids in one request, rooms missing from response are left out
+    pub async fn get_rooms_info(
+        &self,
+        room_ids: &[u64],
+    ) -> Result<HashMap<u64, RoomInfo>, BiliClientError> {
+        let query = room_ids
+            .iter()
+            .map(|id| format!("room_ids={}", id))
+            .collect::<Vec<String>>()
+            .join("&");
+        let request = self
+            .client
+            .get(format!(
+                "https://api.live.bilibili.com/xlive/web-room/v1/index/getRoomBaseInfo?{}&req_biz=link-center",
+                query
+            ))
+            .headers(self.headers.clone());
+        let res = self.send(request).await?;
+        let res = self.parse_response(res).await?;
+        let rooms = res["data"]["by_room_ids"]
+            .as_object()
+            .ok_or(BiliClientError::InvalidResponse)?;
+        Ok(rooms
+            .values()
+            .filter_map(|room| {
+                Some(RoomInfo {
+                    room_id: room["room_id"].as_u64()?,
+                    room_title: room["title"].as_str()?.to_string(),
+                    room_cover_url: room["cover"].as_str().unwrap_or("").to_string(),
+                    room_keyframe_url: room["keyframe"].as_str().unwrap_or("").to_string(),
+                    user_id: room["uid"].as_u64()?,
+                    live_status: room["live_status"].as_u64()? as u8,
+                })
+            })
+            .map(|room| (room.room_id, room))
+            .collect())
+    }
+
     pub async fn get_play_url(
         &self,
         account: &AccountRow,
@@ -402,31 +571,28 @@ impl BiliClient {
     ) -> Result<(String, StreamType), BiliClientError> {
         let mut headers = self.headers.clone();
         headers.insert("cookie", account.cookies.parse().unwrap());
-        let res: PlayUrlResponse = self
+        let request = self
             .client
             .get(format!(
                 "https://api.live.bilibili.com/xlive/web-room/v2/index/getRoomPlayInfo?room_id={}&protocol=1&format=0,1,2&codec=0&qn=10000&platform=h5",
                 room_id
             ))
-            .headers(headers)
-            .send().await?
-            .json().await?;
-        if res.code == 0 {
-            if let Some(stream) = res.data.playurl_info.playurl.stream.first() {
-                // Get fmp4 format
-                if let Some(format) = stream.format.get(1) {
-                    self.get_url_from_format(format)
-                        .await
-                        .ok_or(BiliClientError::InvalidFormat)
-                        .map(|url| (url, StreamType::FMP4))
-                } else if let Some(format) = stream.format.first() {
-                    self.get_url_from_format(format)
-                        .await
-                        .ok_or(BiliClientError::InvalidFormat)
-                        .map(|url| (url, StreamType::TS))
-                } else {
-                    Err(BiliClientError::InvalidResponse)
-                }
+            .headers(headers);
+        let res = self.send(request).await?;
+        let res: PlayUrlResponse = serde_json::from_value(self.parse_response(res).await?)
+            .map_err(|_| BiliClientError::InvalidResponse)?;
+        if let Some(stream) = res.data.playurl_info.playurl.stream.first() {
+            // Get fmp4 format
+            if let Some(format) = stream.format.get(1) {
+                self.get_url_from_format(format)
+                    .await
+                    .ok_or(BiliClientError::InvalidFormat)
+                    .map(|url| (url, StreamType::FMP4))
+            } else if let Some(format) = stream.format.first() {
+                self.get_url_from_format(format)
+                    .await
+                    .ok_or(BiliClientError::InvalidFormat)
+                    .map(|url| (url, StreamType::TS))
             } else {
                 Err(BiliClientError::InvalidResponse)
             }
@@ -479,34 +645,34 @@ impl BiliClient {
         Ok(size)
     }
 
-    // Method from js code
-    pub async fn get_sign(&self, mut parameters: Value) -> Result<String, BiliClientError> {
+    /// Mixin key of WBI signature, cached for WBI_KEY_EXPIRE
+    async fn wbi_key(&self) -> Result<String, BiliClientError> {
+        if let Some(key) = self.wbi_key.read().await.as_ref() {
+            if key.fetched_at.elapsed() < WBI_KEY_EXPIRE {
+                return Ok(key.key.clone());
+            }
+        }
         let table = vec![
             46, 47, 18, 2, 53, 8, 23, 32, 15, 50, 10, 31, 58, 3, 45, 35, 27, 43, 5, 49, 33, 9, 42,
             19, 29, 28, 14, 39, 12, 38, 41, 13, 37, 48, 7, 16, 24, 55, 40, 61, 26, 17, 0, 1, 60,
             51, 30, 4, 22, 25, 54, 21, 56, 59, 6, 63, 57, 62, 11, 36, 20, 34, 44, 52,
         ];
-        let nav_info: Value = self
+        // nav returns -101 without login, but wbi_img is still there
+        let request = self
             .client
             .get("https://api.bilibili.com/x/web-interface/nav")
-            .headers(self.headers.clone())
-            .send()
-            .await?
-            .json()
-            .await?;
+            .headers(self.headers.clone());
+        let nav_info: Value = self.send(request).await?.json().await?;
         let re = Regex::new(r"wbi/(.*).png").unwrap();
-        let img = re
-            .captures(nav_info["data"]["wbi_img"]["img_url"].as_str().unwrap())
-            .unwrap()
-            .get(1)
-            .unwrap()
-            .as_str();
-        let sub = re
-            .captures(nav_info["data"]["wbi_img"]["sub_url"].as_str().unwrap())
-            .unwrap()
-            .get(1)
-            .unwrap()
-            .as_str();
+        let key_of = |url: &Value| {
+            url.as_str()
+                .and_then(|url| re.captures(url))
+                .and_then(|c| c.get(1))
+                .map(|m| m.as_str().to_string())
+                .ok_or(BiliClientError::InvalidResponse)
+        };
+        let img = key_of(&nav_info["data"]["wbi_img"]["img_url"])?;
+        let sub = key_of(&nav_info["data"]["wbi_img"]["sub_url"])?;
         let raw_string = format!("{}{}", img, sub);
         let mut encoded = Vec::new();
         table.into_iter().for_each(|x| {
@@ -514,9 +680,22 @@ impl BiliClient {
                 encoded.push(raw_string.as_bytes()[x]);
             }
         });
+        if encoded.len() < 32 {
+            return Err(BiliClientError::InvalidResponse);
+        }
         // only keep 32 bytes of encoded
         encoded = encoded[0..32].to_vec();
-        let encoded = String::from_utf8(encoded).unwrap();
+        let encoded = String::from_utf8(encoded).map_err(|_| BiliClientError::InvalidResponse)?;
+        *self.wbi_key.write().await = Some(WbiKey {
+            key: encoded.clone(),
+            fetched_at: Instant::now(),
+        });
+        Ok(encoded)
+    }
+
+    // Method from js code
+    pub async fn get_sign(&self, mut parameters: Value) -> Result<String, BiliClientError> {
+        let encoded = self.wbi_key().await?;
         // Timestamp in seconds
         let wts = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
@@ -567,14 +746,13 @@ impl BiliClient {
         let mut headers = self.headers.clone();
         headers.insert("cookie", account.cookies.parse().unwrap());
         let url = format!(
-            "https://member.bilibili.com/preupload?name={}&r=upos&profile=ugcfx/bup",
+            "{}?name={}&r=upos&profile=ugcfx/bup",
+            self.preupload_url,
             video_file.file_name().unwrap().to_str().unwrap()
         );
+        let request = self.client.get(&url).headers(headers);
         let response = self
-            .client
-            .get(&url)
-            .headers(headers)
-            .send()
+            .send(request)
             .await?
             .json::<PreuploadResponse>()
             .await?;
@@ -587,9 +765,8 @@ impl BiliClient {
         video_file: &Path,
     ) -> Result<PostVideoMetaResponse, BiliClientError> {
         let url = format!(
-            "https:{}{}?uploads=&output=json&profile=ugcfx/bup&filesize={}&partsize={}&biz_id={}",
-            preupload_response.endpoint,
-            preupload_response.upos_uri.replace("upos:/", ""),
+            "{}?uploads=&output=json&profile=ugcfx/bup&filesize={}&partsize={}&biz_id={}",
+            upos_url(preupload_response),
             video_file.metadata().unwrap().len(),
             preupload_response.chunk_size,
             preupload_response.biz_id
@@ -605,107 +782,213 @@ impl BiliClient {
         Ok(response)
     }
 
+    /// Upload chunks missing from state concurrently, state is sent to progress after every chunk
     async fn upload_video(
         &self,
-        preupload_response: &PreuploadResponse,
-        post_video_meta_response: &PostVideoMetaResponse,
+        state: &mut UploadState,
         video_file: &Path,
-    ) -> Result<usize, BiliClientError> {
-        let mut file = File::open(video_file).await?;
-        let mut buffer = vec![0; preupload_response.chunk_size];
-        let file_size = video_file.metadata()?.len();
-        let chunk_size = preupload_response.chunk_size as u64; // 确保使用 u64 类型
-        let total_chunks = (file_size as f64 / chunk_size as f64).ceil() as usize; // 计算总分块数
-
-        let start = Instant::now();
-        let mut chunk = 0;
-        let mut read_total = 0;
-        while let Ok(size) = file.read(&mut buffer[read_total..]).await {
-            read_total += size;
-            log::debug!("size: {}, total: {}", size, read_total);
-            if size > 0 && (read_total as u64) < chunk_size {
-                continue;
-            }
-            if size == 0 && read_total == 0 {
-                break;
-            }
-            let url = format!(
-                "https:{}{}?partNumber={}&uploadId={}&chunk={}&chunks={}&size={}&start={}&end={}&total={}",
-                preupload_response.endpoint,
-                preupload_response.upos_uri.replace("upos:/", ""),
-                chunk + 1,
-                post_video_meta_response.upload_id,
-                chunk,
+        options: &UploadOptions,
+        progress: &mpsc::UnboundedSender<UploadState>,
+    ) -> Result<(), BiliClientError> {
+        let total_chunks = state.total_chunks();
+        let done: HashSet<usize> = state.parts.iter().map(|p| p.part_number).collect();
+        let missing: Vec<usize> = (1..=total_chunks).filter(|p| !done.contains(p)).collect();
+        log::info!(
+            "[bili]Upload {} of {} chunks with {} workers",
+            missing.len(),
+            total_chunks,
+            options.workers
+        );
+        let preupload = state.preupload.clone();
+        let meta = state.meta.clone();
+        let file_size = state.file_size;
+        let mut uploads = futures::stream::iter(missing.into_iter().map(|part| {
+            self.upload_chunk(
+                &preupload,
+                &meta,
+                video_file,
+                file_size,
+                part,
                 total_chunks,
-                read_total,
-                chunk * preupload_response.chunk_size,
-                chunk * preupload_response.chunk_size + read_total,
-                video_file.metadata().unwrap().len()
-            );
-            self.client
-                .put(&url)
-                .header("X-Upos-Auth", &preupload_response.auth)
-                .header("Content-Type", "application/octet-stream")
-                .header("Content-Length", read_total.to_string())
-                .body(buffer[..read_total].to_vec())
-                .send()
-                .await?
-                .text()
-                .await?;
-            chunk += 1;
-            read_total = 0;
+                options.retries,
+            )
+        }))
+        .buffer_unordered(options.workers.max(1));
+        let start = Instant::now();
+        let mut uploaded = 0;
+        while let Some(result) = uploads.next().await {
+            let (part, size) = result?;
+            uploaded += size;
+            state.parts.push(part);
+            let _ = progress.send(state.clone());
             log::debug!(
                 "[bili]speed: {:.1} KiB/s",
-                (chunk * preupload_response.chunk_size) as f64
-                    / start.elapsed().as_secs_f64()
-                    / 1024.0
+                uploaded as f64 / start.elapsed().as_secs_f64() / 1024.0
             );
         }
-        Ok(total_chunks)
+        Ok(())
     }
 
-    async fn end_upload(
+    /// Upload one chunk, 5xx, 429 and network errors are retried with exponential backoff
+    #[allow(clippy::too_many_arguments)]
+    async fn upload_chunk(
         &self,
         preupload_response: &PreuploadResponse,
         post_video_meta_response: &PostVideoMetaResponse,
+        video_file: &Path,
+        file_size: u64,
+        part: usize,
         chunks: usize,
-    ) -> Result<(), BiliClientError> {
+        retries: u32,
+    ) -> Result<(UploadedPart, u64), BiliClientError> {
+        let chunk_size = preupload_response.chunk_size as u64;
+        let start = (part as u64 - 1) * chunk_size;
+        let size = chunk_size.min(file_size - start);
+        let mut buffer = vec![0; size as usize];
+        let mut file = File::open(video_file).await?;
+        file.seek(SeekFrom::Start(start)).await?;
+        file.read_exact(&mut buffer).await?;
         let url = format!(
-            "https:{}{}?output=json&name={}&profile=ugcfx/bup&uploadId={}&biz_id={}",
-            preupload_response.endpoint,
-            preupload_response.upos_uri.replace("upos:/", ""),
-            preupload_response.upos_uri,
+            "{}?partNumber={}&uploadId={}&chunk={}&chunks={}&size={}&start={}&end={}&total={}",
+            upos_url(preupload_response),
+            part,
             post_video_meta_response.upload_id,
-            preupload_response.biz_id
+            part - 1,
+            chunks,
+            size,
+            start,
+            start + size,
+            file_size
         );
-        let parts: Vec<Value> = (1..=chunks)
-            .map(|i| json!({ "partNumber": i, "eTag": "etag" }))
-            .collect();
+        let mut attempt = 0;
+        loop {
+            let err = match self
+                .client
+                .put(&url)
+                .header("X-Upos-Auth", &preupload_response.auth)
+                .header("Content-Type", "application/octet-stream")
+                .header("Content-Length", size.to_string())
+                .body(buffer.clone())
+                .send()
+                .await
+            {
+                Ok(resp) if resp.status().is_success() => {
+                    // upos checks eTag of every part on completion
+                    let etag = resp
+                        .headers()
+                        .get("ETag")
+                        .and_then(|v| v.to_str().ok())
+                        .map(|v| v.trim_matches('"').to_string())
+                        .ok_or(BiliClientError::MissingEtag { part })?;
+                    return Ok((
+                        UploadedPart {
+                            part_number: part,
+                            etag,
+                        },
+                        size,
+                    ));
+                }
+                Ok(resp) => {
+                    let status = resp.status();
+                    let err = BiliClientError::UploadFailed {
+                        part,
+                        status: status.as_u16(),
+                    };
+                    if !status.is_server_error() && status != StatusCode::TOO_MANY_REQUESTS {
+                        return Err(err);
+                    }
+                    err
+                }
+                Err(e) => e.into(),
+            };
+            if attempt >= retries {
+                return Err(err);
+            }
+            attempt += 1;
+            log::warn!(
+                "[bili]Upload part {} failed: {}, retry {}/{}",
+                part,
+                err,
+                attempt,
+                retries
+            );
+            tokio::time::sleep(Duration::from_secs(1 << attempt)).await;
+        }
+    }
+
+    async fn end_upload(&self, state: &UploadState) -> Result<(), BiliClientError> {
+        let url = format!(
+            "{}?output=json&name={}&profile=ugcfx/bup&uploadId={}&biz_id={}",
+            upos_url(&state.preupload),
+            state.preupload.upos_uri,
+            state.meta.upload_id,
+            state.preupload.biz_id
+        );
+        let mut parts = state.parts.clone();
+        parts.sort_by_key(|p| p.part_number);
         let body = json!({ "parts": parts });
-        self.client
+        let response = self
+            .client
             .post(&url)
-            .header("X-Upos-Auth", &preupload_response.auth)
+            .header("X-Upos-Auth", &state.preupload.auth)
             .header("Content-Type", "application/json; charset=UTF-8")
             .body(body.to_string())
             .send()
             .await?
-            .text()
+            .json::<Value>()
             .await?;
+        if response["OK"].as_i64() != Some(1) {
+            log::error!("[bili]End upload failed: {}", response);
+            return Err(BiliClientError::InvalidResponse);
+        }
         Ok(())
     }
 
+    /// Upload video file in chunks, ready to be submitted.
+    ///
+    /// Upload continues from saved state if it is still valid for the file,
+    /// progress receives the state every time a chunk is uploaded so it can be persisted.
     pub async fn prepare_video(
         &self,
         account: &AccountRow,
         video_file: &Path,
+        saved: Option<UploadState>,
+        options: &UploadOptions,
+        progress: mpsc::UnboundedSender<UploadState>,
     ) -> Result<profile::Video, BiliClientError> {
-        let preupload = self.preupload_video(account, video_file).await?;
-        let metaposted = self.post_video_meta(&preupload, video_file).await?;
-        let uploaded = self
-            .upload_video(&preupload, &metaposted, video_file)
-            .await?;
-        self.end_upload(&preupload, &metaposted, uploaded).await?;
-        let filename = Path::new(&metaposted.key)
+        let file_size = video_file.metadata()?.len();
+        let mut state = match saved {
+            Some(state) if state.resumable(file_size) => {
+                log::info!(
+                    "[bili]Resume upload {} with {} parts done",
+                    state.meta.upload_id,
+                    state.parts.len()
+                );
+                state
+            }
+            _ => {
+                let preupload = self.preupload_video(account, video_file).await?;
+                let meta = self.post_video_meta(&preupload, video_file).await?;
+                UploadState {
+                    preupload,
+                    meta,
+                    file_size,
+                    parts: Vec::new(),
+                    created_at: chrono::Utc::now().timestamp_millis(),
+                    completed: false,
+                }
+            }
+        };
+        let _ = progress.send(state.clone());
+        // parts of multi-part submissions may be finished by an earlier attempt
+        if !state.completed {
+            self.upload_video(&mut state, video_file, options, &progress)
+                .await?;
+            self.end_upload(&state).await?;
+            state.completed = true;
+            let _ = progress.send(state.clone());
+        }
+        let filename = Path::new(&state.meta.key)
             .file_stem()
             .unwrap()
             .to_str()
@@ -714,7 +997,7 @@ impl BiliClient {
             title: "".to_string(),
             filename: filename.to_string(),
             desc: "".to_string(),
-            cid: preupload.biz_id,
+            cid: state.preupload.biz_id,
         })
     }
 
@@ -722,7 +1005,7 @@ impl BiliClient {
         &self,
         account: &AccountRow,
         profile_template: &Profile,
-        video: &profile::Video,
+        videos: &[profile::Video],
     ) -> Result<VideoSubmitData, BiliClientError> {
         let mut headers = self.headers.clone();
         headers.insert("cookie", account.cookies.parse().unwrap());
@@ -732,35 +1015,54 @@ impl BiliClient {
             account.csrf
         );
         let mut preprofile = profile_template.clone();
-        preprofile.videos.push(video.clone());
-        match self
+        preprofile.videos.extend_from_slice(videos);
+        let request = self
             .client
             .post(&url)
             .headers(headers)
             .header("Content-Type", "application/json; charset=UTF-8")
-            .body(serde_json::ser::to_string(&preprofile).unwrap_or("".to_string()))
-            .send()
-            .await
-        {
-            Ok(raw_resp) => {
-                let json = raw_resp.json().await?;
-                if let Ok(resp) = serde_json::from_value::<GeneralResponse>(json) {
-                    match resp.data {
-                        response::Data::VideoSubmit(data) => Ok(data),
-                        _ => Err(BiliClientError::InvalidResponse),
-                    }
-                } else {
-                    println!("Parse response failed");
-                    Err(BiliClientError::InvalidResponse)
-                }
-            }
-            Err(e) => {
-                println!("Send failed {}", e);
+            .body(serde_json::ser::to_string(&preprofile).unwrap_or("".to_string()));
+        let response = self.send(request).await?;
+        let json = self.parse_response(response).await?;
+        match serde_json::from_value::<GeneralResponse>(json) {
+            Ok(GeneralResponse {
+                data: Some(response::Data::VideoSubmit(data)),
+                ..
+            }) => Ok(data),
+            _ => {
+                log::error!("Parse submit response failed");
                 Err(BiliClientError::InvalidResponse)
             }
         }
     }
 
+    /// Review state of archive submitted by account
+    pub async fn get_archive_state(
+        &self,
+        account: &AccountRow,
+        bvid: &str,
+    ) -> Result<ArchiveState, BiliClientError> {
+        let mut headers = self.headers.clone();
+        headers.insert("cookie", account.cookies.parse().unwrap());
+        let request = self
+            .client
+            .get(format!(
+                "https://member.bilibili.com/x/vupre/web/archive/view?bvid={}",
+                bvid
+            ))
+            .headers(headers);
+        let res = self.send(request).await?;
+        let res = self.parse_response(res).await?;
+        let archive = &res["data"]["archive"];
+        Ok(ArchiveState {
+            state: archive["state"]
+                .as_i64()
+                .ok_or(BiliClientError::InvalidResponse)?,
+            state_desc: archive["state_desc"].as_str().unwrap_or("").to_string(),
+            reject_reason: archive["reject_reason"].as_str().unwrap_or("").to_string(),
+        })
+    }
+
     pub async fn upload_cover(
         &self,
         account: &AccountRow,
@@ -773,29 +1075,21 @@ impl BiliClient {
         let mut headers = self.headers.clone();
         headers.insert("cookie", account.cookies.parse().unwrap());
         let params = [("csrf", account.csrf.clone()), ("cover", cover.to_string())];
-        match self
+        let request = self
             .client
             .post(&url)
             .headers(headers)
             .header("Content-Type", "application/x-www-form-urlencoded")
-            .form(&params)
-            .send()
-            .await
-        {
-            Ok(raw_resp) => {
-                let json = raw_resp.json().await?;
-                if let Ok(resp) = serde_json::from_value::<GeneralResponse>(json) {
-                    match resp.data {
-                        response::Data::Cover(data) => Ok(data.url),
-                        _ => Err(BiliClientError::InvalidResponse),
-                    }
-                } else {
-                    println!("Parse response failed");
-                    Err(BiliClientError::InvalidResponse)
-                }
-            }
-            Err(e) => {
-                println!("Send failed {}", e);
+            .form(&params);
+        let response = self.send(request).await?;
+        let json = self.parse_response(response).await?;
+        match serde_json::from_value::<GeneralResponse>(json) {
+            Ok(GeneralResponse {
+                data: Some(response::Data::Cover(data)),
+                ..
+            }) => Ok(data.url),
+            _ => {
+                log::error!("Parse cover response failed");
                 Err(BiliClientError::InvalidResponse)
             }
         }
@@ -822,14 +1116,14 @@ impl BiliClient {
             ("csrf", &account.csrf),
             ("csrf_token", &account.csrf),
         ];
-        let _ = self
+        let request = self
             .client
             .post(&url)
             .headers(headers)
             .header("Content-Type", "application/x-www-form-urlencoded")
-            .form(&params)
-            .send()
-            .await?;
+            .form(&params);
+        let response = self.send(request).await?;
+        self.parse_response(response).await?;
         Ok(())
     }
 }
@@ -1,15 +1,54 @@
 use custom_error::custom_error;
+use serde::Serialize;
 
 custom_error! {pub BiliClientError
     InvalidResponse = "Invalid response",
     InitClientError = "Client init error",
-    InvalidCode = "Invalid Code",
     InvalidValue = "Invalid value",
     InvalidUrl = "Invalid url",
     InvalidFormat = "Invalid stream format",
     EmptyCache = "Empty cache",
-    ClientError{err: reqwest::Error} = "Client error",
-    IOError{err: std::io::Error} = "IO error",
+    UploadFailed{part: usize, status: u16} = "Upload part {part} failed with status {status}",
+    MissingEtag{part: usize} = "Upload part {part} returned no ETag",
+    ApiError{endpoint: String, status: u16, code: i64, message: String} = "{endpoint} failed: {message} (code {code}, HTTP {status})",
+    ClientError{err: reqwest::Error} = "Client error: {err}",
+    IOError{err: std::io::Error} = "IO error: {err}",
+}
+
+/// Known categories of API errors
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum ApiErrorKind {
+    /// cookies are expired or csrf is invalid
+    NotLoggedIn,
+    RateLimited,
+    /// request is blocked by risk control, usually -352 or HTTP 412
+    RiskControl,
+    InvalidParams,
+    Other,
+}
+
+impl ApiErrorKind {
+    pub fn from_response(status: u16, code: i64) -> ApiErrorKind {
+        match (status, code) {
+            (401, _) | (_, -101) | (_, -111) => ApiErrorKind::NotLoggedIn,
+            (429, _) | (_, -509) | (_, -799) => ApiErrorKind::RateLimited,
+            (412, _) | (_, -412) | (_, -352) => ApiErrorKind::RiskControl,
+            (400, _) | (_, -400) => ApiErrorKind::InvalidParams,
+            _ => ApiErrorKind::Other,
+        }
+    }
+}
+
+impl BiliClientError {
+    /// Category of API error, None for other errors
+    pub fn kind(&self) -> Option<ApiErrorKind> {
+        match self {
+            BiliClientError::ApiError { status, code, .. } => {
+                Some(ApiErrorKind::from_response(*status, *code))
+            }
+            _ => None,
+        }
+    }
 }
 
 impl From<reqwest::Error> for BiliClientError {
@@ -1,5 +1,14 @@
+use crate::db::ProfileTemplateRow;
+use crate::recorder::naming::{self, NameContext};
 use serde::{Deserialize, Serialize};
 
+/// Scheduled publish time must be at least 2 hours later
+const MIN_SCHEDULE_DELAY: i64 = 2 * 60 * 60;
+/// and within 15 days
+const MAX_SCHEDULE_DELAY: i64 = 15 * 24 * 60 * 60;
+const MAX_TITLE_CHARS: usize = 80;
+const MAX_DESC_CHARS: usize = 2000;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Profile {
     pub videos: Vec<Video>,
@@ -25,9 +34,99 @@ pub struct Profile {
     pub up_close_reply: bool,
     pub up_close_danmu: bool,
     pub web_os: u8,
+    /// scheduled publish time in seconds, published right after review if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dtime: Option<i64>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// Same as the default profile of frontend
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            videos: Vec::new(),
+            cover: "".to_string(),
+            cover43: None,
+            title: "".to_string(),
+            copyright: 1,
+            tid: 27,
+            tag: "".to_string(),
+            desc_format_id: 9999,
+            desc: "".to_string(),
+            recreate: -1,
+            dynamic: "".to_string(),
+            interactive: 0,
+            act_reserve_create: 0,
+            no_disturbance: 0,
+            no_reprint: 0,
+            subtitle: Subtitle::default(),
+            dolby: 0,
+            lossless_music: 0,
+            up_selection_reply: false,
+            up_close_reply: false,
+            up_close_danmu: false,
+            web_os: 0,
+            dtime: None,
+        }
+    }
+}
+
+impl Profile {
+    /// Profile from template, placeholders in title, desc and dynamic are rendered with ctx
+    pub fn from_template(
+        template: &ProfileTemplateRow,
+        ctx: &NameContext,
+    ) -> Result<Profile, String> {
+        let title = naming::render(&template.title, ctx)?;
+        let desc = naming::render(&template.desc, ctx)?;
+        Ok(Profile {
+            title: title.chars().take(MAX_TITLE_CHARS).collect(),
+            tid: template.tid,
+            tag: template.tag.clone(),
+            desc: desc.chars().take(MAX_DESC_CHARS).collect(),
+            copyright: template.copyright,
+            dynamic: naming::render(&template.dynamic, ctx)?,
+            ..Default::default()
+        })
+    }
+
+    pub fn validate_template(template: &ProfileTemplateRow) -> Result<(), String> {
+        if template.name.trim().is_empty() {
+            return Err("Template name is empty".to_string());
+        }
+        if template.copyright != 1 && template.copyright != 2 {
+            return Err(format!("Invalid copyright {}", template.copyright));
+        }
+        if template.tid == 0 {
+            return Err("Invalid area".to_string());
+        }
+        naming::validate(&template.title)?;
+        naming::validate(&template.desc)?;
+        naming::validate(&template.dynamic)
+    }
+
+    /// Check scheduled publish time against the window allowed at now, in seconds
+    pub fn validate_schedule(&self, now: i64) -> Result<(), String> {
+        let dtime = match self.dtime {
+            Some(dtime) => dtime,
+            None => return Ok(()),
+        };
+        if dtime < now + MIN_SCHEDULE_DELAY {
+            return Err(format!(
+                "Scheduled publish time must be at least {} hours later",
+                MIN_SCHEDULE_DELAY / 3600
+            ));
+        }
+        if dtime > now + MAX_SCHEDULE_DELAY {
+            return Err(format!(
+                "Scheduled publish time must be within {} days",
+                MAX_SCHEDULE_DELAY / 86400
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Subtitle {
     open: u8,
     lan: String,
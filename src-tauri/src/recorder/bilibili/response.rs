@@ -2,10 +2,12 @@ use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct GeneralResponse {
-    pub code: u8,
+    pub code: i64,
     pub message: String,
-    pub ttl: u8,
-    pub data: Data,
+    #[serde(default)]
+    pub ttl: i64,
+    /// null if code is not 0
+    pub data: Option<Data>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -26,7 +28,7 @@ pub struct CoverData {
     pub url: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PreuploadResponse {
     pub endpoint: String,
     pub upos_uri: String,
@@ -35,7 +37,7 @@ pub struct PreuploadResponse {
     pub biz_id: u64,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PostVideoMetaResponse {
     pub bucket: String,
     pub key: String,
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use tokio::sync::{Mutex, Semaphore, SemaphorePermit};
+
+use super::errors::ApiErrorKind;
+
+/// API requests running at the same time
+const MAX_CONCURRENT_REQUESTS: usize = 4;
+/// Interval between requests to the same endpoint if not listed in ENDPOINT_INTERVALS
+const DEFAULT_INTERVAL: Duration = Duration::from_millis(200);
+/// Endpoints polled for every room get a longer interval
+const ENDPOINT_INTERVALS: &[(&str, Duration)] = &[
+    (
+        "api.bilibili.com/x/web-interface/nav",
+        Duration::from_secs(1),
+    ),
+    (
+        "api.bilibili.com/x/space/wbi/acc/info",
+        Duration::from_secs(1),
+    ),
+    (
+        "api.live.bilibili.com/room/v1/Room/get_info",
+        Duration::from_millis(300),
+    ),
+    (
+        "api.live.bilibili.com/xlive/web-room/v1/index/getRoomBaseInfo",
+        Duration::from_secs(1),
+    ),
+    (
+        "api.live.bilibili.com/xlive/web-room/v2/index/getRoomPlayInfo",
+        Duration::from_millis(500),
+    ),
+    ("api.live.bilibili.com/msg/send", Duration::from_secs(1)),
+];
+/// Backoff after the first rate limited or blocked response, doubled on every following one
+const BACKOFF_BASE: Duration = Duration::from_secs(5);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+#[derive(Default)]
+struct EndpointState {
+    /// earliest time of the next request
+    next_at: Option<Instant>,
+    /// rate limited responses in a row
+    failures: u32,
+}
+
+/// Endpoint of url as host and path, query is ignored
+pub fn endpoint(url: &reqwest::Url) -> String {
+    format!("{}{}", url.host_str().unwrap_or(""), url.path())
+}
+
+fn backoff(failures: u32) -> Duration {
+    (BACKOFF_BASE * 2u32.pow(failures.saturating_sub(1).min(10))).min(MAX_BACKOFF)
+}
+
+/// Spaces out API requests of all clients.
+///
+/// Requests to the same endpoint wait for its interval, and at most MAX_CONCURRENT_REQUESTS run at
+/// the same time. Rate limited endpoints are backed off, risk control blocks the whole IP so it
+/// backs off every endpoint.
+pub struct RequestScheduler {
+    permits: Semaphore,
+    endpoints: Mutex<HashMap<String, EndpointState>>,
+    /// global backoff from risk control
+    blocked_until: Mutex<(Option<Instant>, u32)>,
+}
+
+impl Default for RequestScheduler {
+    fn default() -> Self {
+        RequestScheduler {
+            permits: Semaphore::new(MAX_CONCURRENT_REQUESTS),
+            endpoints: Mutex::new(HashMap::new()),
+            blocked_until: Mutex::new((None, 0)),
+        }
+    }
+}
+
+impl RequestScheduler {
+    fn interval(endpoint: &str) -> Duration {
+        ENDPOINT_INTERVALS
+            .iter()
+            .find(|(e, _)| *e == endpoint)
+            .map(|(_, i)| *i)
+            .unwrap_or(DEFAULT_INTERVAL)
+    }
+
+    /// Wait for a slot of endpoint, the request is sent while the permit is held
+    pub async fn acquire(&self, endpoint: &str) -> SemaphorePermit<'_> {
+        let now = Instant::now();
+        let start = {
+            let mut endpoints = self.endpoints.lock().await;
+            let state = endpoints.entry(endpoint.to_string()).or_default();
+            let mut start = state.next_at.unwrap_or(now).max(now);
+            if let Some(blocked_until) = self.blocked_until.lock().await.0 {
+                start = start.max(blocked_until);
+            }
+            // reserve the slot so concurrent callers queue up behind it
+            state.next_at = Some(start + Self::interval(endpoint));
+            start
+        };
+        if start > now {
+            log::debug!("Request to {} delayed {:?}", endpoint, start - now);
+            tokio::time::sleep_until(start.into()).await;
+        }
+        self.permits.acquire().await.unwrap()
+    }
+
+    /// Record result of a request to endpoint, kind is None for successful ones
+    pub async fn report(&self, endpoint: &str, kind: Option<ApiErrorKind>) {
+        let now = Instant::now();
+        match kind {
+            Some(ApiErrorKind::RiskControl) => {
+                let mut blocked = self.blocked_until.lock().await;
+                blocked.1 += 1;
+                let delay = backoff(blocked.1);
+                log::warn!(
+                    "Risk control triggered by {}, backing off {:?}",
+                    endpoint,
+                    delay
+                );
+                blocked.0 = Some(now + delay);
+            }
+            Some(ApiErrorKind::RateLimited) => {
+                let mut endpoints = self.endpoints.lock().await;
+                let state = endpoints.entry(endpoint.to_string()).or_default();
+                state.failures += 1;
+                let delay = backoff(state.failures);
+                log::warn!("{} is rate limited, backing off {:?}", endpoint, delay);
+                state.next_at = Some(state.next_at.unwrap_or(now).max(now + delay));
+            }
+            Some(_) => {}
+            None => {
+                if let Some(state) = self.endpoints.lock().await.get_mut(endpoint) {
+                    state.failures = 0;
+                }
+                let mut blocked = self.blocked_until.lock().await;
+                if blocked.0.is_some_and(|until| until <= now) {
+                    *blocked = (None, 0);
+                }
+            }
+        }
+    }
+}
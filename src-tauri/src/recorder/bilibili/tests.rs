@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+
+use super::errors::BiliClientError;
+use super::response::{PostVideoMetaResponse, PreuploadResponse};
+use super::upload::{UploadOptions, UploadState, UploadedPart};
+use super::BiliClient;
+use crate::db::AccountRow;
+
+const CHUNK_SIZE: usize = 1024;
+/// 4 full chunks and a short last one
+const FILE_SIZE: usize = CHUNK_SIZE * 4 + 100;
+const UPOS_PATH: &str = "/ugcfx/test.mp4";
+
+/// Requests received by mock upos server
+#[derive(Default)]
+struct MockState {
+    /// statuses returned for a part before it is accepted
+    failures: HashMap<usize, Vec<u16>>,
+    omit_etag: bool,
+    preuploads: usize,
+    /// part numbers of every PUT, failed ones included
+    attempts: Vec<usize>,
+    /// content of accepted parts
+    parts: HashMap<usize, Vec<u8>>,
+    in_flight: usize,
+    max_in_flight: usize,
+    completed: Option<Value>,
+}
+
+fn query(req: &Request<Body>) -> HashMap<String, String> {
+    req.uri()
+        .query()
+        .unwrap_or("")
+        .split('&')
+        .filter_map(|kv| kv.split_once('='))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+fn json_response(value: Value) -> Response<Body> {
+    Response::new(Body::from(value.to_string()))
+}
+
+async fn handle(
+    state: Arc<Mutex<MockState>>,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let host = req.headers()["host"].to_str().unwrap().to_string();
+    let query = query(&req);
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    if path == "/preupload" {
+        state.lock().unwrap().preuploads += 1;
+        return Ok(json_response(json!({
+            "endpoint": format!("http://{}", host),
+            "upos_uri": format!("upos:/{}", UPOS_PATH),
+            "auth": "auth",
+            "chunk_size": CHUNK_SIZE,
+            "biz_id": 1,
+        })));
+    }
+    assert_eq!(path, UPOS_PATH);
+    assert_eq!(req.headers()["X-Upos-Auth"], "auth");
+    if method == Method::POST && query.contains_key("uploads") {
+        return Ok(json_response(json!({
+            "bucket": "ugcfx",
+            "key": "/test.mp4",
+            "upload_id": "upload",
+        })));
+    }
+    if method == Method::POST {
+        assert_eq!(query["uploadId"], "upload");
+        let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+        state.lock().unwrap().completed = Some(serde_json::from_slice(&body).unwrap());
+        return Ok(json_response(json!({ "OK": 1 })));
+    }
+    assert_eq!(method, Method::PUT);
+    let part: usize = query["partNumber"].parse().unwrap();
+    let failure = {
+        let mut state = state.lock().unwrap();
+        state.attempts.push(part);
+        state.in_flight += 1;
+        state.max_in_flight = state.max_in_flight.max(state.in_flight);
+        let failures = state.failures.entry(part).or_default();
+        (!failures.is_empty()).then(|| failures.remove(0))
+    };
+    let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+    // keep the request open so parallel chunks overlap
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let mut state = state.lock().unwrap();
+    state.in_flight -= 1;
+    if let Some(status) = failure {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::from_u16(status).unwrap();
+        return Ok(response);
+    }
+    state.parts.insert(part, body.to_vec());
+    let mut response = Response::new(Body::empty());
+    if !state.omit_etag {
+        response
+            .headers_mut()
+            .insert("ETag", format!("\"etag-{}\"", part).parse().unwrap());
+    }
+    Ok(response)
+}
+
+/// Start mock upos server, returns its address
+fn start_server(state: Arc<Mutex<MockState>>) -> String {
+    let make_svc = make_service_fn(move |_conn| {
+        let state = state.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| handle(state.clone(), req))) }
+    });
+    let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+    let addr = server.local_addr();
+    tokio::spawn(server);
+    format!("http://{}", addr)
+}
+
+fn video_file(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("upos-{}-{}.mp4", name, std::process::id()));
+    let content: Vec<u8> = (0..FILE_SIZE).map(|i| (i % 251) as u8).collect();
+    std::fs::write(&path, content).unwrap();
+    path
+}
+
+fn account() -> AccountRow {
+    AccountRow {
+        uid: 1,
+        name: "test".to_string(),
+        avatar: "".to_string(),
+        csrf: "csrf".to_string(),
+        cookies: "SESSDATA=test".to_string(),
+        created_at: "".to_string(),
+    }
+}
+
+/// Upload file to mock server, progress states are dropped
+async fn upload(
+    url: &str,
+    file: &Path,
+    saved: Option<UploadState>,
+    retries: u32,
+) -> Result<super::profile::Video, BiliClientError> {
+    let client = BiliClient::new()
+        .unwrap()
+        .with_preupload_url(&format!("{}/preupload", url));
+    let (progress, _rx) = mpsc::unbounded_channel();
+    let options = UploadOptions {
+        workers: 3,
+        retries,
+    };
+    client
+        .prepare_video(&account(), file, saved, &options, progress)
+        .await
+}
+
+fn completed_parts(state: &MockState) -> Vec<(usize, String)> {
+    state.completed.as_ref().unwrap()["parts"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| {
+            (
+                p["partNumber"].as_u64().unwrap() as usize,
+                p["eTag"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect()
+}
+
+fn expected_parts() -> Vec<(usize, String)> {
+    (1..=5).map(|p| (p, format!("etag-{}", p))).collect()
+}
+
+/// State of an upload to mock server with first `parts` parts done
+fn saved_state(url: &str, parts: usize, completed: bool) -> UploadState {
+    UploadState {
+        preupload: PreuploadResponse {
+            endpoint: url.to_string(),
+            upos_uri: format!("upos:/{}", UPOS_PATH),
+            auth: "auth".to_string(),
+            chunk_size: CHUNK_SIZE,
+            biz_id: 1,
+        },
+        meta: PostVideoMetaResponse {
+            bucket: "ugcfx".to_string(),
+            key: "/test.mp4".to_string(),
+            upload_id: "upload".to_string(),
+        },
+        file_size: FILE_SIZE as u64,
+        parts: (1..=parts)
+            .map(|p| UploadedPart {
+                part_number: p,
+                etag: format!("etag-{}", p),
+            })
+            .collect(),
+        created_at: chrono::Utc::now().timestamp_millis(),
+        completed,
+    }
+}
+
+#[tokio::test]
+async fn upload_retries_failed_chunks() {
+    let state = Arc::new(Mutex::new(MockState::default()));
+    state.lock().unwrap().failures = HashMap::from([(2, vec![503]), (3, vec![429])]);
+    let url = start_server(state.clone());
+    let file = video_file("retry");
+    let video = upload(&url, &file, None, 3).await.unwrap();
+    assert_eq!(video.filename, "test");
+    assert_eq!(video.cid, 1);
+
+    let state = state.lock().unwrap();
+    assert_eq!(state.preuploads, 1);
+    assert!(state.max_in_flight > 1);
+    for part in 1..=5 {
+        let attempts = state.attempts.iter().filter(|p| **p == part).count();
+        assert_eq!(attempts, if part == 2 || part == 3 { 2 } else { 1 });
+    }
+    let uploaded: Vec<u8> = (1..=5).flat_map(|p| state.parts[&p].clone()).collect();
+    assert_eq!(uploaded, std::fs::read(&file).unwrap());
+    assert_eq!(completed_parts(&state), expected_parts());
+    let _ = std::fs::remove_file(file);
+}
+
+#[tokio::test]
+async fn upload_resumes_saved_state() {
+    let state = Arc::new(Mutex::new(MockState::default()));
+    let url = start_server(state.clone());
+    let file = video_file("resume");
+    let saved = saved_state(&url, 2, false);
+    upload(&url, &file, Some(saved), 3).await.unwrap();
+
+    let state = state.lock().unwrap();
+    assert_eq!(state.preuploads, 0);
+    let mut attempts = state.attempts.clone();
+    attempts.sort();
+    assert_eq!(attempts, vec![3, 4, 5]);
+    assert_eq!(completed_parts(&state), expected_parts());
+    let _ = std::fs::remove_file(file);
+}
+
+#[tokio::test]
+async fn upload_skips_completed_state() {
+    let state = Arc::new(Mutex::new(MockState::default()));
+    let url = start_server(state.clone());
+    let file = video_file("completed");
+    let video = upload(&url, &file, Some(saved_state(&url, 5, true)), 3)
+        .await
+        .unwrap();
+    assert_eq!(video.filename, "test");
+
+    let state = state.lock().unwrap();
+    assert_eq!(state.preuploads, 0);
+    assert!(state.attempts.is_empty());
+    assert!(state.completed.is_none());
+    let _ = std::fs::remove_file(file);
+}
+
+#[tokio::test]
+async fn upload_fails_on_client_error() {
+    let state = Arc::new(Mutex::new(MockState::default()));
+    state.lock().unwrap().failures = HashMap::from([(1, vec![403])]);
+    let url = start_server(state.clone());
+    let file = video_file("forbidden");
+    let result = upload(&url, &file, None, 3).await;
+    assert!(matches!(
+        result,
+        Err(BiliClientError::UploadFailed {
+            part: 1,
+            status: 403
+        })
+    ));
+    let state = state.lock().unwrap();
+    assert_eq!(state.attempts.iter().filter(|p| **p == 1).count(), 1);
+    assert!(state.completed.is_none());
+    let _ = std::fs::remove_file(file);
+}
+
+#[tokio::test]
+async fn upload_fails_without_etag() {
+    let state = Arc::new(Mutex::new(MockState::default()));
+    state.lock().unwrap().omit_etag = true;
+    let url = start_server(state.clone());
+    let file = video_file("etag");
+    let result = upload(&url, &file, None, 0).await;
+    assert!(matches!(result, Err(BiliClientError::MissingEtag { .. })));
+    assert!(state.lock().unwrap().completed.is_none());
+    let _ = std::fs::remove_file(file);
+}
@@ -0,0 +1,113 @@
+use serde::{Deserialize, Serialize};
+
+use super::response::{PostVideoMetaResponse, PreuploadResponse};
+
+/// upos upload ids are not valid for long, older states are not resumed
+const RESUME_EXPIRE_MS: i64 = 12 * 60 * 60 * 1000;
+
+/// How chunks of a video are uploaded
+#[derive(Clone, Debug)]
+pub struct UploadOptions {
+    /// chunks uploaded at the same time
+    pub workers: usize,
+    /// retries of every chunk before the upload fails
+    pub retries: u32,
+}
+
+impl Default for UploadOptions {
+    fn default() -> Self {
+        UploadOptions {
+            workers: 3,
+            retries: 3,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UploadedPart {
+    #[serde(rename = "partNumber")]
+    pub part_number: usize,
+    #[serde(rename = "eTag")]
+    pub etag: String,
+}
+
+/// State of a chunked upload, persisted to resume it after restart
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UploadState {
+    pub preupload: PreuploadResponse,
+    pub meta: PostVideoMetaResponse,
+    pub file_size: u64,
+    pub parts: Vec<UploadedPart>,
+    /// milliseconds
+    pub created_at: i64,
+    /// file is complete on upos and waits for submission
+    #[serde(default)]
+    pub completed: bool,
+}
+
+impl UploadState {
+    pub fn total_chunks(&self) -> usize {
+        self.file_size.div_ceil(self.preupload.chunk_size as u64) as usize
+    }
+
+    pub fn uploaded_bytes(&self) -> u64 {
+        let chunk_size = self.preupload.chunk_size as u64;
+        self.parts
+            .iter()
+            .map(|p| {
+                let start = (p.part_number as u64 - 1) * chunk_size;
+                chunk_size.min(self.file_size - start)
+            })
+            .sum()
+    }
+
+    /// Whether the state can be used to continue uploading file of file_size
+    pub fn resumable(&self, file_size: u64) -> bool {
+        self.file_size == file_size
+            && chrono::Utc::now().timestamp_millis() - self.created_at < RESUME_EXPIRE_MS
+    }
+}
+
+/// Progress of an upload, emitted as `upload:{video_id}` event
+#[derive(Serialize, Clone, Debug)]
+pub struct UploadProgress {
+    pub video_id: i64,
+    /// bytes
+    pub uploaded: u64,
+    pub total: u64,
+    /// part number of the last uploaded chunk
+    pub chunk: usize,
+    pub uploaded_chunks: usize,
+    pub total_chunks: usize,
+    /// bytes per second
+    pub speed: f64,
+    /// estimated seconds left
+    pub eta: f64,
+}
+
+impl UploadProgress {
+    /// Progress of state, speed only counts bytes uploaded after `resumed` bytes in `elapsed` seconds
+    pub fn new(video_id: i64, state: &UploadState, resumed: u64, elapsed: f64) -> Self {
+        let uploaded = state.uploaded_bytes();
+        let speed = if elapsed > 0.0 {
+            uploaded.saturating_sub(resumed) as f64 / elapsed
+        } else {
+            0.0
+        };
+        let eta = if speed > 0.0 {
+            (state.file_size - uploaded) as f64 / speed
+        } else {
+            0.0
+        };
+        UploadProgress {
+            video_id,
+            uploaded,
+            total: state.file_size,
+            chunk: state.parts.last().map(|p| p.part_number).unwrap_or(0),
+            uploaded_chunks: state.parts.len(),
+            total_chunks: state.total_chunks(),
+            speed,
+            eta,
+        }
+    }
+}
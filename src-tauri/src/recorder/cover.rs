@@ -0,0 +1,259 @@
+use base64::Engine;
+use ffmpeg_sidecar::{
+    command::FfmpegCommand,
+    event::{FfmpegEvent, LogLevel},
+};
+use std::path::Path;
+
+use super::probe_duration;
+
+pub const DEFAULT_CANDIDATES: usize = 6;
+
+/// Scene score threshold of ffmpeg select filter, higher picks fewer frames
+const SCENE_THRESHOLD: f64 = 0.3;
+/// Width of candidate images, height keeps aspect ratio
+const COVER_WIDTH: u32 = 1280;
+
+/// Aspect ratios of covers accepted by submission
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CoverAspect {
+    /// 16:9, `cover` of profile
+    Wide,
+    /// 4:3, `cover43` of profile
+    Standard,
+}
+
+impl CoverAspect {
+    fn name(&self) -> &'static str {
+        match self {
+            CoverAspect::Wide => "16x9",
+            CoverAspect::Standard => "4x3",
+        }
+    }
+
+    fn size(&self) -> (u32, u32) {
+        match self {
+            CoverAspect::Wide => (1280, 720),
+            CoverAspect::Standard => (960, 720),
+        }
+    }
+}
+
+/// Prefix of candidate files, candidates are stored next to clip as `{stem}.cover{NN}.jpg`
+fn candidate_prefix(clip: &str) -> String {
+    let path = Path::new(clip);
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("clip");
+    match path.parent() {
+        Some(dir) => format!("{}/{}.cover", dir.to_str().unwrap_or("."), stem),
+        None => format!("{}.cover", stem),
+    }
+}
+
+/// Cover chosen by user is stored next to clip as `{stem}.cover.jpg`, apart from candidates
+pub fn cover_file(clip: &str) -> String {
+    format!("{}.jpg", candidate_prefix(clip))
+}
+
+/// Existing candidates of clip, sorted by index
+pub fn candidates(clip: &str) -> Vec<String> {
+    let prefix = candidate_prefix(clip);
+    let dir = Path::new(&prefix).parent().unwrap_or(Path::new("."));
+    let mut ret: Vec<String> = match std::fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .filter_map(|e| e.path().to_str().map(|s| s.to_string()))
+            .filter(|p| {
+                p.strip_prefix(&prefix)
+                    .and_then(|p| p.strip_suffix(".jpg"))
+                    .is_some_and(|index| {
+                        !index.is_empty() && index.chars().all(|c| c.is_ascii_digit())
+                    })
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    ret.sort();
+    ret
+}
+
+pub fn remove_candidates(clip: &str) {
+    for file in candidates(clip) {
+        if let Err(e) = std::fs::remove_file(&file) {
+            log::warn!("Remove cover candidate {} failed: {}", file, e);
+        }
+    }
+}
+
+/// Extract up to count frames at scene changes, evenly spaced frames fill the rest
+/// if the clip does not change enough. Old candidates are replaced.
+pub fn extract_candidates(clip: &str, count: usize) -> Vec<String> {
+    remove_candidates(clip);
+    let prefix = candidate_prefix(clip);
+    run(FfmpegCommand::new()
+        .input(clip)
+        .args([
+            "-vf",
+            &format!(
+                "select='gt(scene,{})',scale={}:-2",
+                SCENE_THRESHOLD, COVER_WIDTH
+            ),
+            "-vsync",
+            "vfr",
+            "-frames:v",
+            &count.to_string(),
+            "-q:v",
+            "2",
+        ])
+        .output(format!("{}%02d.jpg", prefix)));
+    let found = candidates(clip).len();
+    if found < count {
+        let duration = probe_duration(clip).unwrap_or(0.0);
+        let slots = (count - found) as f64;
+        for index in found..count {
+            // middle of every slot
+            let time = duration * ((index - found) as f64 + 0.5) / slots;
+            run(FfmpegCommand::new()
+                .args(["-ss", &format!("{:.2}", time)])
+                .input(clip)
+                .args([
+                    "-vf",
+                    &format!("scale={}:-2", COVER_WIDTH),
+                    "-frames:v",
+                    "1",
+                    "-q:v",
+                    "2",
+                ])
+                .output(format!("{}{:02}.jpg", prefix, index + 1)));
+        }
+    }
+    candidates(clip)
+}
+
+/// Whether file is an image that can be used as cover
+pub fn is_image(file: &str) -> bool {
+    matches!(
+        Path::new(file)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase())
+            .as_deref(),
+        Some("jpg" | "jpeg" | "png" | "webp" | "bmp")
+    )
+}
+
+/// Image file as data url, which is accepted as cover by upload api
+pub fn data_url(file: &str) -> std::io::Result<String> {
+    let content = std::fs::read(file)?;
+    let mime = match Path::new(file)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+        .as_deref()
+    {
+        Some("png") => "image/png",
+        Some("webp") => "image/webp",
+        _ => "image/jpeg",
+    };
+    Ok(format!(
+        "data:{};base64,{}",
+        mime,
+        base64::engine::general_purpose::STANDARD.encode(content)
+    ))
+}
+
+/// Cover already uploaded to a server, which is submitted as is
+pub fn is_remote(source: &str) -> bool {
+    source.starts_with("http://") || source.starts_with("https://")
+}
+
+/// Scale and center crop image to the size of aspect, re-encoded as JPEG data url.
+///
+/// source is a local image file or a data url, http(s) url is returned unchanged.
+/// ffmpeg is run in place, async callers should use spawn_blocking.
+pub fn prepare(source: &str, aspect: CoverAspect) -> Result<String, String> {
+    if is_remote(source) {
+        return Ok(source.to_string());
+    }
+    let output = std::env::temp_dir().join(format!("cover-{}-{}.jpg", nanos(), aspect.name()));
+    let output = output.to_str().ok_or("Invalid cover path")?;
+    convert(source, aspect, output)?;
+    let ret = data_url(output).map_err(|e| {
+        format!(
+            "Convert cover {} failed: {}",
+            source.chars().take(64).collect::<String>(),
+            e
+        )
+    });
+    let _ = std::fs::remove_file(output);
+    ret
+}
+
+/// Crop local image to cover of clip, returns path of the cover file
+pub fn save(source: &str, clip: &str) -> Result<String, String> {
+    let output = cover_file(clip);
+    // old cover is removed so a failed conversion is not taken as done
+    let _ = std::fs::remove_file(&output);
+    convert(source, CoverAspect::Wide, &output)?;
+    if !Path::new(&output).exists() {
+        return Err(format!("Convert cover {} failed", source));
+    }
+    Ok(output)
+}
+
+/// Scale and center crop local image or data url to the size of aspect into output
+fn convert(source: &str, aspect: CoverAspect, output: &str) -> Result<(), String> {
+    let input = if source.starts_with("data:") {
+        let content = source
+            .split_once(";base64,")
+            .and_then(|(_, data)| base64::engine::general_purpose::STANDARD.decode(data).ok())
+            .ok_or("Invalid data url of cover")?;
+        let input = std::env::temp_dir().join(format!("cover-{}-input", nanos()));
+        std::fs::write(&input, content).map_err(|e| e.to_string())?;
+        input
+    } else {
+        if !Path::new(source).exists() {
+            return Err(format!("Cover {} not found", source));
+        }
+        source.into()
+    };
+    let (width, height) = aspect.size();
+    run(FfmpegCommand::new()
+        .input(input.to_str().ok_or("Invalid cover path")?)
+        .args([
+            "-vf",
+            &format!(
+                "scale={w}:{h}:force_original_aspect_ratio=increase,crop={w}:{h}",
+                w = width,
+                h = height
+            ),
+            "-frames:v",
+            "1",
+            "-q:v",
+            "2",
+            "-y",
+        ])
+        .output(output));
+    if source.starts_with("data:") {
+        let _ = std::fs::remove_file(&input);
+    }
+    Ok(())
+}
+
+/// Unique part of temp file names
+fn nanos() -> u128 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0)
+}
+
+fn run(command: &mut FfmpegCommand) {
+    match command.spawn() {
+        Ok(mut child) => child.iter().unwrap().for_each(|e| {
+            if let FfmpegEvent::Log(LogLevel::Error, e) = e {
+                log::error!("Error: {}", e)
+            }
+        }),
+        Err(e) => log::error!("Spawn ffmpeg failed: {}", e),
+    }
+}
@@ -0,0 +1,169 @@
+use serde::{Deserialize, Serialize};
+
+const VIDEO_CODECS: [&str; 2] = ["libx264", "libx265"];
+const AUDIO_CODECS: [&str; 2] = ["aac", "libopus"];
+const X264_PRESETS: [&str; 9] = [
+    "ultrafast",
+    "superfast",
+    "veryfast",
+    "faster",
+    "fast",
+    "medium",
+    "slow",
+    "slower",
+    "veryslow",
+];
+
+/// Named ffmpeg encoding options for clip output, stored in config
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct EncodingPreset {
+    pub name: String,
+    pub video_codec: String,
+    /// constant rate factor, encoder default if None
+    pub crf: Option<u8>,
+    /// x264/x265 speed preset, like "medium"
+    pub speed: Option<String>,
+    /// output is scaled if any of width and height is set, keeping aspect ratio for the other
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fps: Option<u32>,
+    pub audio_codec: String,
+    /// kbps
+    pub audio_bitrate: Option<u32>,
+}
+
+impl Default for EncodingPreset {
+    /// Same as clips before presets are introduced
+    fn default() -> Self {
+        EncodingPreset {
+            name: "default".into(),
+            video_codec: "libx264".into(),
+            crf: None,
+            speed: None,
+            width: None,
+            height: None,
+            fps: None,
+            audio_codec: "aac".into(),
+            audio_bitrate: None,
+        }
+    }
+}
+
+impl EncodingPreset {
+    pub fn builtin() -> Vec<EncodingPreset> {
+        vec![
+            EncodingPreset::default(),
+            EncodingPreset {
+                name: "upload 1080p60".into(),
+                crf: Some(20),
+                speed: Some("medium".into()),
+                height: Some(1080),
+                fps: Some(60),
+                audio_bitrate: Some(320),
+                ..Default::default()
+            },
+            EncodingPreset {
+                name: "preview 720p".into(),
+                crf: Some(28),
+                speed: Some("veryfast".into()),
+                height: Some(720),
+                fps: Some(30),
+                audio_bitrate: Some(128),
+                ..Default::default()
+            },
+            EncodingPreset {
+                name: "archive quality".into(),
+                video_codec: "libx265".into(),
+                crf: Some(18),
+                speed: Some("slow".into()),
+                audio_bitrate: Some(320),
+                ..Default::default()
+            },
+        ]
+    }
+
+    /// Check options before ffmpeg is spawned
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Preset name is empty".into());
+        }
+        if !VIDEO_CODECS.contains(&self.video_codec.as_str()) {
+            return Err(format!("Unsupported video codec: {}", self.video_codec));
+        }
+        if !AUDIO_CODECS.contains(&self.audio_codec.as_str()) {
+            return Err(format!("Unsupported audio codec: {}", self.audio_codec));
+        }
+        if let Some(crf) = self.crf {
+            if crf > 51 {
+                return Err(format!("CRF {} is out of range [0, 51]", crf));
+            }
+        }
+        if let Some(speed) = &self.speed {
+            if !X264_PRESETS.contains(&speed.as_str()) {
+                return Err(format!("Unsupported speed preset: {}", speed));
+            }
+        }
+        for size in [self.width, self.height].into_iter().flatten() {
+            if size == 0 || size > 7680 || size % 2 != 0 {
+                return Err(format!("Invalid resolution: {}", size));
+            }
+        }
+        if let Some(fps) = self.fps {
+            if fps == 0 || fps > 120 {
+                return Err(format!("Frame rate {} is out of range [1, 120]", fps));
+            }
+        }
+        if let Some(bitrate) = self.audio_bitrate {
+            if !(32..=512).contains(&bitrate) {
+                return Err(format!("Audio bitrate {}k is out of range [32, 512]", bitrate));
+            }
+        }
+        Ok(())
+    }
+
+    /// Scale filter for resolution, None if resolution is not changed
+    pub fn scale_filter(&self) -> Option<String> {
+        match (self.width, self.height) {
+            (None, None) => None,
+            (w, h) => Some(format!(
+                "scale={}:{}",
+                w.map(|w| w as i64).unwrap_or(-2),
+                h.map(|h| h as i64).unwrap_or(-2)
+            )),
+        }
+    }
+
+    /// Codec arguments, filters are not included
+    pub fn codec_args(&self) -> Vec<String> {
+        let mut args = vec!["-c:v".to_string(), self.video_codec.clone()];
+        if let Some(crf) = self.crf {
+            args.extend(["-crf".to_string(), crf.to_string()]);
+        }
+        if let Some(speed) = &self.speed {
+            args.extend(["-preset".to_string(), speed.clone()]);
+        }
+        args.extend(self.audio_codec_args());
+        args
+    }
+
+    pub fn audio_codec_args(&self) -> Vec<String> {
+        let mut args = vec!["-c:a".to_string(), self.audio_codec.clone()];
+        if let Some(bitrate) = self.audio_bitrate {
+            args.extend(["-b:a".to_string(), format!("{}k", bitrate)]);
+        }
+        args
+    }
+
+    /// Full output arguments for a single input
+    pub fn output_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(filter) = self.scale_filter() {
+            args.extend(["-vf".to_string(), filter]);
+        }
+        if let Some(fps) = self.fps {
+            args.extend(["-r".to_string(), fps.to_string()]);
+        }
+        args.extend(self.codec_args());
+        args
+    }
+}
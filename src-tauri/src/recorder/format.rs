@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+
+use super::encoding::EncodingPreset;
+
+/// Animations are meant for short loops, longer ones get too large
+const MAX_ANIMATION_SECONDS: f64 = 30.0;
+
+/// Container and codec of clip output
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ClipFormat {
+    #[default]
+    Mp4,
+    /// audio stream only, encoded with audio options of preset
+    Audio,
+    Gif,
+    Webp,
+}
+
+/// Options for GIF and WebP output
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AnimationOptions {
+    pub fps: u32,
+    pub width: u32,
+}
+
+impl Default for AnimationOptions {
+    fn default() -> Self {
+        AnimationOptions { fps: 15, width: 480 }
+    }
+}
+
+impl ClipFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ClipFormat::Mp4 => "mp4",
+            ClipFormat::Audio => "audio",
+            ClipFormat::Gif => "gif",
+            ClipFormat::Webp => "webp",
+        }
+    }
+
+    /// Format of clip file, files without known extension are taken as mp4
+    pub fn from_file(file: &str) -> ClipFormat {
+        match std::path::Path::new(file)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase())
+            .as_deref()
+        {
+            Some("m4a") | Some("opus") => ClipFormat::Audio,
+            Some("gif") => ClipFormat::Gif,
+            Some("webp") => ClipFormat::Webp,
+            _ => ClipFormat::Mp4,
+        }
+    }
+
+    pub fn extension(&self, preset: &EncodingPreset) -> &'static str {
+        match self {
+            ClipFormat::Mp4 => "mp4",
+            ClipFormat::Audio if preset.audio_codec == "libopus" => "opus",
+            ClipFormat::Audio => "m4a",
+            ClipFormat::Gif => "gif",
+            ClipFormat::Webp => "webp",
+        }
+    }
+
+    pub fn is_animation(&self) -> bool {
+        matches!(self, ClipFormat::Gif | ClipFormat::Webp)
+    }
+
+    pub fn validate(&self, animation: &AnimationOptions, duration: f64) -> Result<(), String> {
+        if !self.is_animation() {
+            return Ok(());
+        }
+        if duration > MAX_ANIMATION_SECONDS {
+            return Err(format!(
+                "{} is limited to {}s, got {:.1}s",
+                self.name(),
+                MAX_ANIMATION_SECONDS,
+                duration
+            ));
+        }
+        if animation.fps == 0 || animation.fps > 50 {
+            return Err(format!("Frame rate {} is out of range [1, 50]", animation.fps));
+        }
+        if animation.width < 16 || animation.width > 1920 || animation.width % 2 != 0 {
+            return Err(format!("Invalid width: {}", animation.width));
+        }
+        Ok(())
+    }
+
+    /// Output arguments of audio format
+    pub fn audio_args(preset: &EncodingPreset) -> Vec<String> {
+        let mut args = vec!["-vn".to_string()];
+        args.extend(preset.audio_codec_args());
+        args
+    }
+
+    /// Filters from video label input to label output for animation formats
+    pub fn animation_filter(&self, animation: &AnimationOptions, input: &str, output: &str) -> String {
+        let scale = format!(
+            "[{}]fps={},scale={}:-2:flags=lanczos",
+            input, animation.fps, animation.width
+        );
+        match self {
+            // palette from the clip itself keeps colors far better than the default one
+            ClipFormat::Gif => format!(
+                "{},split[p0][p1];[p0]palettegen=stats_mode=diff[pal];[p1][pal]paletteuse=dither=bayer[{}]",
+                scale, output
+            ),
+            _ => format!("{}[{}]", scale, output),
+        }
+    }
+
+    /// Codec arguments of animation formats, loop forever
+    pub fn animation_args(&self) -> Vec<String> {
+        let args: &[&str] = match self {
+            ClipFormat::Gif => &["-an", "-loop", "0"],
+            ClipFormat::Webp => &[
+                "-an",
+                "-c:v",
+                "libwebp",
+                "-lossless",
+                "0",
+                "-quality",
+                "75",
+                "-loop",
+                "0",
+            ],
+            _ => &[],
+        };
+        args.iter().map(|a| a.to_string()).collect()
+    }
+}
@@ -0,0 +1,83 @@
+use serde::{Deserialize, Serialize};
+
+/// Crop window on source video, all values are relative to source size in [0, 1]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CropRegion {
+    pub x: f64,
+    pub y: f64,
+    pub w: f64,
+    pub h: f64,
+}
+
+/// Layout for vertical short video, regions are stacked from top to bottom
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct VerticalLayout {
+    pub width: u32,
+    pub height: u32,
+    pub regions: Vec<CropRegion>,
+    /// fill the rest of canvas with blurred source, otherwise black
+    pub blur_background: bool,
+}
+
+impl VerticalLayout {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.width == 0 || self.height == 0 || self.width % 2 != 0 || self.height % 2 != 0 {
+            return Err(format!("Invalid canvas size {}x{}", self.width, self.height));
+        }
+        // vertical shorts are 9:16, like 1080x1920
+        if self.width as u64 * 16 != self.height as u64 * 9 {
+            return Err(format!("Canvas size {}x{} is not 9:16", self.width, self.height));
+        }
+        if self.regions.is_empty() {
+            return Err("Layout has no crop region".into());
+        }
+        for r in self.regions.iter() {
+            if r.x < 0.0 || r.y < 0.0 || r.w <= 0.0 || r.h <= 0.0 || r.x + r.w > 1.0 || r.y + r.h > 1.0
+            {
+                return Err(format!(
+                    "Crop region [{}, {}, {}, {}] is out of source",
+                    r.x, r.y, r.w, r.h
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Filter graph from input video `input` into output label `output`
+    pub fn filter_graph(&self, input: &str, output: &str) -> String {
+        let (w, h) = (self.width, self.height);
+        let n = self.regions.len();
+        let mut filters = Vec::new();
+        let splits: String = (0..n).map(|i| format!("[s{}]", i)).collect();
+        filters.push(format!("[{}]split={}[bg]{}", input, n + 1, splits));
+        let fill = if self.blur_background {
+            "boxblur=20:5"
+        } else {
+            "drawbox=x=0:y=0:w=iw:h=ih:color=black:t=fill"
+        };
+        filters.push(format!(
+            "[bg]scale={w}:{h}:force_original_aspect_ratio=increase,crop={w}:{h},{fill}[bgf]",
+        ));
+        for (i, r) in self.regions.iter().enumerate() {
+            filters.push(format!(
+                "[s{i}]crop=iw*{}:ih*{}:iw*{}:ih*{},scale={w}:-2[r{i}]",
+                r.w, r.h, r.x, r.y
+            ));
+        }
+        let regions: String = (0..n).map(|i| format!("[r{}]", i)).collect();
+        if n == 1 {
+            filters.push(format!("{}null[stack]", regions));
+        } else {
+            filters.push(format!("{}vstack=inputs={}[stack]", regions, n));
+        }
+        // stacked regions may be taller than canvas
+        filters.push(format!(
+            "[stack]scale={w}:{h}:force_original_aspect_ratio=decrease:force_divisible_by=2[fit]"
+        ));
+        filters.push(format!(
+            "[bgf][fit]overlay=(W-w)/2:(H-h)/2,setsar=1,format=yuv420p[{}]",
+            output
+        ));
+        filters.join(";")
+    }
+}
@@ -0,0 +1,165 @@
+use ffmpeg_sidecar::{
+    command::FfmpegCommand,
+    event::{FfmpegEvent, LogLevel},
+};
+use serde::{Deserialize, Serialize};
+
+/// Targets of EBU R128 loudnorm filter
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct LoudnormOptions {
+    /// integrated loudness in LUFS
+    #[serde(default = "default_target")]
+    pub target: f64,
+    /// maximum true peak in dBTP
+    #[serde(default = "default_true_peak")]
+    pub true_peak: f64,
+    /// loudness range in LU
+    #[serde(default = "default_lra")]
+    pub lra: f64,
+}
+
+fn default_target() -> f64 {
+    -14.0
+}
+
+fn default_true_peak() -> f64 {
+    -1.0
+}
+
+fn default_lra() -> f64 {
+    11.0
+}
+
+impl Default for LoudnormOptions {
+    fn default() -> Self {
+        LoudnormOptions {
+            target: default_target(),
+            true_peak: default_true_peak(),
+            lra: default_lra(),
+        }
+    }
+}
+
+/// Loudness measured before and after normalization
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LoudnessReport {
+    pub target: f64,
+    pub input_i: f64,
+    pub input_tp: f64,
+    pub input_lra: f64,
+    pub output_i: f64,
+    pub output_tp: f64,
+    pub output_lra: f64,
+}
+
+/// JSON printed by loudnorm filter, numbers are quoted
+#[derive(Deserialize)]
+struct Measurement {
+    input_i: String,
+    input_tp: String,
+    input_lra: String,
+    input_thresh: String,
+    output_i: String,
+    output_tp: String,
+    output_lra: String,
+    target_offset: String,
+}
+
+impl LoudnormOptions {
+    /// Ranges accepted by ffmpeg loudnorm filter
+    pub fn validate(&self) -> Result<(), String> {
+        if !(-70.0..=-5.0).contains(&self.target) {
+            return Err(format!("Target {} LUFS is out of range [-70, -5]", self.target));
+        }
+        if !(-9.0..=0.0).contains(&self.true_peak) {
+            return Err(format!("True peak {} dBTP is out of range [-9, 0]", self.true_peak));
+        }
+        if !(1.0..=50.0).contains(&self.lra) {
+            return Err(format!("Loudness range {} LU is out of range [1, 50]", self.lra));
+        }
+        Ok(())
+    }
+
+    fn filter(&self) -> String {
+        format!(
+            "loudnorm=I={}:TP={}:LRA={}:print_format=json",
+            self.target, self.true_peak, self.lra
+        )
+    }
+}
+
+/// Normalize audio of file in place with two-pass loudnorm.
+///
+/// First pass measures the file, second pass re-encodes audio with codec_args using the measurement,
+/// video stream is copied. extra_args are appended to output, like metadata.
+pub fn normalize(
+    file: &str,
+    options: &LoudnormOptions,
+    codec_args: &[String],
+    extra_args: &[String],
+) -> Result<LoudnessReport, String> {
+    let first = measure(
+        FfmpegCommand::new()
+            .input(file)
+            .args(["-map", "0:a:0", "-af", &options.filter(), "-f", "null"])
+            .output("-"),
+    )
+    .ok_or(format!("Measure loudness of {} failed", file))?;
+    let filter = format!(
+        "{}:measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:offset={}:linear=true",
+        options.filter(),
+        first.input_i,
+        first.input_tp,
+        first.input_lra,
+        first.input_thresh,
+        first.target_offset
+    );
+    let path = std::path::Path::new(file);
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("mp4");
+    let temp = path.with_extension(format!("loudnorm.{}", ext));
+    let temp = temp.to_str().ok_or("Invalid file path")?;
+    let second = measure(
+        FfmpegCommand::new()
+            .input(file)
+            .args(["-map", "0", "-c", "copy"])
+            .args(codec_args)
+            // loudnorm resamples to 192k internally
+            .args(["-af", &filter, "-ar", "48000"])
+            .args(extra_args)
+            .output(temp),
+    );
+    let second = match second {
+        Some(second) => second,
+        None => {
+            let _ = std::fs::remove_file(temp);
+            return Err(format!("Normalize loudness of {} failed", file));
+        }
+    };
+    std::fs::rename(temp, file).map_err(|e| e.to_string())?;
+    let parse = |v: &str| v.trim().parse::<f64>().unwrap_or(f64::NEG_INFINITY);
+    Ok(LoudnessReport {
+        target: options.target,
+        input_i: parse(&first.input_i),
+        input_tp: parse(&first.input_tp),
+        input_lra: parse(&first.input_lra),
+        output_i: parse(&second.output_i),
+        output_tp: parse(&second.output_tp),
+        output_lra: parse(&second.output_lra),
+    })
+}
+
+/// Run ffmpeg with loudnorm filter and parse the JSON it prints at the end
+fn measure(command: &mut FfmpegCommand) -> Option<Measurement> {
+    let mut log = String::new();
+    command.spawn().ok()?.iter().ok()?.for_each(|e| match e {
+        FfmpegEvent::Log(LogLevel::Error, e) => log::error!("Error: {}", e),
+        FfmpegEvent::Log(_, line) => {
+            log += &line;
+            log += "\n";
+        }
+        _ => {}
+    });
+    let start = log.rfind('{')?;
+    let end = log[start..].find('}')? + start;
+    serde_json::from_str(&log[start..=end]).ok()
+}
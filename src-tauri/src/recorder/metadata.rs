@@ -0,0 +1,70 @@
+use serde::Serialize;
+
+use super::loudness::LoudnessReport;
+
+/// Range of one archive used in clip, offsets are relative to first sequence of live_id
+#[derive(Serialize, Clone, Debug)]
+pub struct ClipSource {
+    pub live_id: u64,
+    pub start: f64,
+    pub end: f64,
+}
+
+/// Origin of a clip, embedded into container and written to optional sidecar JSON
+#[derive(Serialize, Clone, Debug)]
+pub struct ClipMetadata {
+    pub title: String,
+    pub streamer: String,
+    pub room_id: u64,
+    pub sources: Vec<ClipSource>,
+    /// seconds
+    pub duration: f64,
+    /// creation time of the first source archive
+    pub recorded_at: String,
+    pub created_at: String,
+    pub tool: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub loudness: Option<LoudnessReport>,
+}
+
+impl ClipMetadata {
+    pub fn tool_version() -> String {
+        format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+    }
+
+    /// Metadata arguments for ffmpeg output, custom keys are kept by use_metadata_tags
+    pub fn ffmpeg_args(&self) -> Vec<String> {
+        let sources = self
+            .sources
+            .iter()
+            .map(|s| format!("{}:{:.1}-{:.1}", s.live_id, s.start, s.end))
+            .collect::<Vec<String>>()
+            .join(",");
+        let mut args = vec!["-movflags".to_string(), "+use_metadata_tags".to_string()];
+        let tags = [
+            ("title", self.title.clone()),
+            ("artist", self.streamer.clone()),
+            ("date", self.recorded_at.clone()),
+            (
+                "comment",
+                format!("Clipped from live room {} by {}", self.room_id, self.tool),
+            ),
+            ("room_id", self.room_id.to_string()),
+            ("live_id", self.sources.first().map(|s| s.live_id).unwrap_or(0).to_string()),
+            ("source_range", sources),
+            ("tool", self.tool.clone()),
+        ];
+        for (key, value) in tags {
+            args.extend(["-metadata".to_string(), format!("{}={}", key, value)]);
+        }
+        args
+    }
+
+    /// Write metadata next to clip as `{clip}.json`
+    pub fn write_sidecar(&self, file_name: &str) -> std::io::Result<String> {
+        let path = format!("{}.json", file_name);
+        let content = serde_json::to_string_pretty(self)?;
+        std::fs::write(&path, content)?;
+        Ok(path)
+    }
+}
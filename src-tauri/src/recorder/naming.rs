@@ -0,0 +1,154 @@
+use chrono::{DateTime, Local};
+use std::fmt::Write;
+
+pub const DEFAULT_CLIP_TEMPLATE: &str = "[{room_id}]{title:5}_{date}_{duration}";
+pub const DEFAULT_ARCHIVE_TEMPLATE: &str = "[{room_id}]{live_id}_{date}_{duration}";
+
+/// Names longer than this are truncated, leaving room for extension and collision suffix
+const MAX_NAME_BYTES: usize = 200;
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Values for placeholders in name template
+pub struct NameContext {
+    pub room_id: u64,
+    pub streamer: String,
+    pub title: String,
+    pub live_id: u64,
+    /// seconds relative to first sequence
+    pub offset: f64,
+    /// seconds
+    pub duration: f64,
+    pub date: DateTime<Local>,
+}
+
+pub fn default_clip_template() -> String {
+    DEFAULT_CLIP_TEMPLATE.to_string()
+}
+
+pub fn default_archive_template() -> String {
+    DEFAULT_ARCHIVE_TEMPLATE.to_string()
+}
+
+/// Check template by rendering it with sample values
+pub fn validate(template: &str) -> Result<(), String> {
+    let ctx = NameContext {
+        room_id: 0,
+        streamer: String::new(),
+        title: String::new(),
+        live_id: 0,
+        offset: 0.0,
+        duration: 0.0,
+        date: Local::now(),
+    };
+    render(template, &ctx).map(|_| ())
+}
+
+/// Render template like `[{room_id}]{title:5}_{date:%Y%m%d}`.
+///
+/// Placeholders: room_id, streamer, title, live_id, offset, duration, date.
+/// Argument after `:` truncates text to N chars, or formats date with chrono format.
+/// Literal braces are written as `{{` and `}}`.
+pub fn render(template: &str, ctx: &NameContext) -> Result<String, String> {
+    let mut ret = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find(['{', '}']) {
+        ret += &rest[..start];
+        let escape = &rest[start..start + 1];
+        if rest[start + 1..].starts_with(escape) {
+            ret += escape;
+            rest = &rest[start + 2..];
+            continue;
+        }
+        // single closing brace is kept as is
+        if escape == "}" {
+            ret += escape;
+            rest = &rest[start + 1..];
+            continue;
+        }
+        let end = rest[start..]
+            .find('}')
+            .ok_or(format!("Unclosed placeholder in {}", template))?
+            + start;
+        let placeholder = &rest[start + 1..end];
+        let (key, arg) = match placeholder.split_once(':') {
+            Some((key, arg)) => (key, Some(arg)),
+            None => (placeholder, None),
+        };
+        let value = match key {
+            "room_id" => ctx.room_id.to_string(),
+            "live_id" => ctx.live_id.to_string(),
+            "streamer" => truncate(&ctx.streamer, arg)?,
+            "title" => truncate(&ctx.title, arg)?,
+            "offset" => format!("{:.1}", ctx.offset),
+            "duration" => format!("{:.1}", ctx.duration),
+            "date" => {
+                let format = arg.unwrap_or("%m%d%H%M%S");
+                // invalid format fails on display instead of parse
+                let mut date = String::new();
+                write!(date, "{}", ctx.date.format(format))
+                    .map_err(|_| format!("Invalid date format {}", format))?;
+                date
+            }
+            _ => return Err(format!("Unknown placeholder {{{}}}", key)),
+        };
+        ret += &value;
+        rest = &rest[end + 1..];
+    }
+    ret += rest;
+    Ok(ret)
+}
+
+fn truncate(text: &str, arg: Option<&str>) -> Result<String, String> {
+    match arg {
+        Some(n) => {
+            let n: usize = n
+                .parse()
+                .map_err(|_| format!("Invalid length {} in placeholder", n))?;
+            Ok(text.chars().take(n).collect())
+        }
+        None => Ok(text.to_string()),
+    }
+}
+
+/// Make name valid as file name on all platforms
+pub fn sanitize(name: &str) -> String {
+    let mut ret: String = name
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    if ret.len() > MAX_NAME_BYTES {
+        let mut end = MAX_NAME_BYTES;
+        while !ret.is_char_boundary(end) {
+            end -= 1;
+        }
+        ret.truncate(end);
+    }
+    // windows does not allow trailing dots and spaces
+    let ret = ret.trim_end_matches(['.', ' ']).trim_start().to_string();
+    if ret.is_empty() {
+        return "clip".to_string();
+    }
+    let stem = ret.split('.').next().unwrap_or("").to_uppercase();
+    if RESERVED_NAMES.contains(&stem.as_str()) {
+        return format!("_{}", ret);
+    }
+    ret
+}
+
+/// Path in dir that does not exist yet, ` (n)` is appended to name on collision
+pub fn unique_path(dir: &str, name: &str, ext: &str) -> String {
+    let mut path = format!("{}/{}.{}", dir, name, ext);
+    let mut index = 1;
+    while std::path::Path::new(&path).exists() {
+        path = format!("{}/{} ({}).{}", dir, name, index, ext);
+        index += 1;
+    }
+    path
+}
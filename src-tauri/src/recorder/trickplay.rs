@@ -0,0 +1,136 @@
+use ffmpeg_sidecar::{
+    command::FfmpegCommand,
+    event::{FfmpegEvent, LogLevel},
+};
+use std::path::Path;
+
+/// Seconds between thumbnails, every segment is taken as 1s like clips
+pub const INTERVAL: usize = 10;
+pub const VTT_FILE: &str = "thumbnails.vtt";
+const TILE_WIDTH: u32 = 160;
+const TILE_HEIGHT: u32 = 90;
+const COLUMNS: usize = 10;
+const ROWS: usize = 10;
+/// Segments covered by one sprite sheet
+pub const SHEET_SEGMENTS: usize = INTERVAL * COLUMNS * ROWS;
+
+fn sprite_name(index: usize) -> String {
+    format!("sprite{}.jpg", index)
+}
+
+/// Number of sheets for segments, incomplete sheet is only counted if session is finished
+fn sheet_count(segments: usize, finished: bool) -> usize {
+    if finished {
+        segments.div_ceil(SHEET_SEGMENTS)
+    } else {
+        segments / SHEET_SEGMENTS
+    }
+}
+
+fn thumbnail_count(segments: usize, finished: bool) -> usize {
+    if finished {
+        segments.div_ceil(INTERVAL)
+    } else {
+        segments / SHEET_SEGMENTS * COLUMNS * ROWS
+    }
+}
+
+/// Whether sprites in work_dir are behind segments, it only checks files so it is cheap
+pub fn pending(work_dir: &str, segments: usize, finished: bool) -> bool {
+    let sheets = sheet_count(segments, finished);
+    if sheets == 0 {
+        return false;
+    }
+    if !Path::new(&format!("{}/{}", work_dir, sprite_name(sheets - 1))).exists() {
+        return true;
+    }
+    // incomplete sheet of finished session may be left from an earlier run
+    let cues = std::fs::read_to_string(format!("{}/{}", work_dir, VTT_FILE))
+        .map(|c| c.matches(" --> ").count())
+        .unwrap_or(0);
+    cues != thumbnail_count(segments, finished)
+}
+
+/// Generate sprite sheets and WebVTT track of a session into work_dir.
+///
+/// segments are paths of segment files in order, header is the init segment of fMP4 stream.
+/// Complete sheets are generated once, the last incomplete sheet is generated only if session is finished.
+pub fn generate(work_dir: &str, header: Option<&str>, segments: &[String], finished: bool) {
+    let full = segments.len() / SHEET_SEGMENTS;
+    for index in 0..sheet_count(segments.len(), finished) {
+        let sprite = format!("{}/{}", work_dir, sprite_name(index));
+        if index < full && Path::new(&sprite).exists() {
+            continue;
+        }
+        let end = segments.len().min((index + 1) * SHEET_SEGMENTS);
+        let mut file_list = String::new();
+        if let Some(header) = header {
+            file_list += header;
+            file_list += "|";
+        }
+        file_list += &segments[index * SHEET_SEGMENTS..end].join("|");
+        log::info!("Generate trickplay sprite {}", sprite);
+        FfmpegCommand::new()
+            .args(["-i", &format!("concat:{}", file_list)])
+            .args([
+                "-vf",
+                &format!(
+                    "fps=1/{},scale={w}:{h}:force_original_aspect_ratio=decrease,pad={w}:{h}:(ow-iw)/2:(oh-ih)/2,tile={}x{}",
+                    INTERVAL,
+                    COLUMNS,
+                    ROWS,
+                    w = TILE_WIDTH,
+                    h = TILE_HEIGHT
+                ),
+                "-frames:v",
+                "1",
+                "-q:v",
+                "5",
+                "-y",
+            ])
+            .output(&sprite)
+            .spawn()
+            .unwrap()
+            .iter()
+            .unwrap()
+            .for_each(|e| {
+                if let FfmpegEvent::Log(LogLevel::Error, e) = e {
+                    log::error!("Error: {}", e)
+                }
+            });
+    }
+    let vtt = format!("{}/{}", work_dir, VTT_FILE);
+    if let Err(e) = std::fs::write(&vtt, webvtt(segments.len(), finished)) {
+        log::error!("Write thumbnails track failed: {}", e);
+    }
+}
+
+/// WebVTT thumbnails track, every cue points to a tile in sprite sheet by media fragment
+fn webvtt(segments: usize, finished: bool) -> String {
+    let mut content = "WEBVTT\n".to_string();
+    for index in 0..thumbnail_count(segments, finished) {
+        let start = index * INTERVAL;
+        let end = segments.min(start + INTERVAL);
+        let tile = index % (COLUMNS * ROWS);
+        content += &format!(
+            "\n{} --> {}\n{}#xywh={},{},{},{}\n",
+            vtt_time(start),
+            vtt_time(end),
+            sprite_name(index / (COLUMNS * ROWS)),
+            tile % COLUMNS * TILE_WIDTH as usize,
+            tile / COLUMNS * TILE_HEIGHT as usize,
+            TILE_WIDTH,
+            TILE_HEIGHT
+        );
+    }
+    content
+}
+
+fn vtt_time(seconds: usize) -> String {
+    format!(
+        "{:02}:{:02}:{:02}.000",
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
@@ -1,7 +1,9 @@
 use crate::db::{AccountRow, Database, RecordRow};
-use crate::recorder::bilibili::UserInfo;
-use crate::recorder::RecorderError;
-use crate::recorder::{bilibili::RoomInfo, BiliRecorder};
+use crate::recorder::bilibili::{BiliClient, UserInfo};
+use crate::recorder::{
+    ClipOptions, ClipRange, ClipResult, CompilationOptions, MergedSession, RecorderError,
+};
+use crate::recorder::{bilibili::RoomInfo, BiliRecorder, SessionEnd};
 use crate::Config;
 use custom_error::custom_error;
 use dashmap::DashMap;
@@ -10,9 +12,13 @@ use hyper::{
     Body, Request, Response, Server,
 };
 use std::net::SocketAddr;
+use std::time::Duration;
 use std::{convert::Infallible, sync::Arc};
 use tauri::AppHandle;
-use tokio::{net::TcpListener, sync::RwLock};
+use tokio::{
+    net::TcpListener,
+    sync::{mpsc, RwLock},
+};
 
 #[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
 pub struct RecorderList {
@@ -30,17 +36,24 @@ pub struct RecorderInfo {
     pub live_status: bool,
 }
 
+/// Interval of live status polling
+const STATUS_INTERVAL: Duration = Duration::from_secs(10);
+/// Rooms queried in one status request
+const ROOM_BATCH_SIZE: usize = 30;
+
 pub struct RecorderManager {
     app_handle: AppHandle,
+    client: Arc<BiliClient>,
     config: Arc<RwLock<Config>>,
     recorders: Arc<DashMap<u64, BiliRecorder>>,
     hls_server_addr: Arc<RwLock<Option<SocketAddr>>>,
+    session_end: mpsc::UnboundedSender<SessionEnd>,
 }
 
 custom_error! {pub RecorderManagerError
     AlreadyExisted { room_id: u64 } = "Recorder {room_id} already existed",
     NotFound {room_id: u64 } = "Recorder {room_id} not found",
-    RecorderError { err: RecorderError } = "Recorder error",
+    RecorderError { err: RecorderError } = "Recorder error: {err}",
     IOError {err: std::io::Error } = "IO error",
     HLSError { err: hyper::Error } = "HLS server error",
 }
@@ -71,12 +84,20 @@ impl From<RecorderManagerError> for String {
 
 impl RecorderManager {
 
-    pub fn new(app_handle: AppHandle, config: Arc<RwLock<Config>>) -> RecorderManager {
+    /// session_end receives sessions ended in all rooms
+    pub fn new(
+        app_handle: AppHandle,
+        client: Arc<BiliClient>,
+        config: Arc<RwLock<Config>>,
+        session_end: mpsc::UnboundedSender<SessionEnd>,
+    ) -> RecorderManager {
         RecorderManager {
             app_handle,
+            client,
             config,
             recorders: Arc::new(DashMap::new()),
             hls_server_addr: Arc::new(RwLock::new(None)),
+            session_end,
         }
     }
 
@@ -90,6 +111,41 @@ impl RecorderManager {
         Ok(())
     }
 
+    /// Poll live status of all rooms every STATUS_INTERVAL, ROOM_BATCH_SIZE rooms per request.
+    ///
+    /// Stream urls are only requested by recorders of live rooms.
+    pub async fn run_status_poller(&self) {
+        loop {
+            tokio::time::sleep(STATUS_INTERVAL).await;
+            let recorders: Vec<BiliRecorder> =
+                self.recorders.iter().map(|r| r.value().clone()).collect();
+            let mut rooms = Vec::new();
+            for recorder in recorders {
+                // batch endpoint only accepts real room ids
+                let room_id = recorder.room_info.read().await.room_id;
+                rooms.push((room_id, recorder));
+            }
+            for batch in rooms.chunks(ROOM_BATCH_SIZE) {
+                let room_ids: Vec<u64> = batch.iter().map(|(id, _)| *id).collect();
+                let mut infos = match self.client.get_rooms_info(&room_ids).await {
+                    Ok(infos) => infos,
+                    Err(e) => {
+                        log::error!("Poll live status failed: {}", e);
+                        continue;
+                    }
+                };
+                for (room_id, recorder) in batch {
+                    match infos.remove(room_id) {
+                        Some(info) => {
+                            recorder.update_status(info, false).await;
+                        }
+                        None => log::warn!("Live status of room {} is missing", room_id),
+                    }
+                }
+            }
+        }
+    }
+
     pub async fn add_recorder(
         &self,
         webid: &str,
@@ -103,11 +159,13 @@ impl RecorderManager {
         }
         let recorder = BiliRecorder::new(
             self.app_handle.clone(),
+            &self.client,
             webid,
             db,
             room_id,
             account,
             self.config.clone(),
+            self.session_end.clone(),
         )
         .await?;
         self.recorders.insert(room_id, recorder);
@@ -137,13 +195,16 @@ impl RecorderManager {
         output_path: &str,
         room_id: u64,
         d: f64,
-    ) -> Result<String, RecorderManagerError> {
+    ) -> Result<ClipResult, RecorderManagerError> {
         let recorder = self.recorders.get(&room_id);
         if recorder.is_none() {
             return Err(RecorderManagerError::NotFound { room_id });
         }
         let recorder = recorder.unwrap();
-        Ok(recorder.value().clip(room_id, d, output_path).await?)
+        Ok(recorder
+            .value()
+            .clip(room_id, d, output_path, &ClipOptions::default())
+            .await?)
     }
 
     pub async fn clip_range(
@@ -153,7 +214,66 @@ impl RecorderManager {
         ts: u64,
         start: f64,
         end: f64,
-    ) -> Result<String, RecorderManagerError> {
+        options: &ClipOptions,
+    ) -> Result<ClipResult, RecorderManagerError> {
+        let recorder = self.recorders.get(&room_id);
+        if recorder.is_none() {
+            return Err(RecorderManagerError::NotFound { room_id });
+        }
+        let recorder = recorder.unwrap();
+        Ok(recorder
+            .value()
+            .clip_range(ts, start, end, output_path, options)
+            .await?)
+    }
+
+    pub async fn clip_time_range(
+        &self,
+        output_path: &str,
+        room_id: u64,
+        start: i64,
+        end: i64,
+        options: &ClipOptions,
+    ) -> Result<ClipResult, RecorderManagerError> {
+        let recorder = self.recorders.get(&room_id);
+        if recorder.is_none() {
+            return Err(RecorderManagerError::NotFound { room_id });
+        }
+        let recorder = recorder.unwrap();
+        Ok(recorder
+            .value()
+            .clip_time_range(start, end, output_path, options)
+            .await?)
+    }
+
+    pub async fn clip_merged_range(
+        &self,
+        output_path: &str,
+        room_id: u64,
+        live_ids: &[u64],
+        start: f64,
+        end: f64,
+        options: &ClipOptions,
+    ) -> Result<ClipResult, RecorderManagerError> {
+        let recorder = self.recorders.get(&room_id);
+        if recorder.is_none() {
+            return Err(RecorderManagerError::NotFound { room_id });
+        }
+        let recorder = recorder.unwrap();
+        Ok(recorder
+            .value()
+            .clip_merged_range(live_ids, start, end, output_path, options)
+            .await?)
+    }
+
+    pub async fn clip_compilation(
+        &self,
+        output_path: &str,
+        room_id: u64,
+        ranges: &[ClipRange],
+        compilation: &CompilationOptions,
+        options: &ClipOptions,
+    ) -> Result<(ClipResult, f64), RecorderManagerError> {
         let recorder = self.recorders.get(&room_id);
         if recorder.is_none() {
             return Err(RecorderManagerError::NotFound { room_id });
@@ -161,7 +281,7 @@ impl RecorderManager {
         let recorder = recorder.unwrap();
         Ok(recorder
             .value()
-            .clip_range(ts, start, end, output_path)
+            .clip_compilation(ranges, compilation, output_path, options)
             .await?)
     }
 
@@ -213,6 +333,17 @@ impl RecorderManager {
         }
     }
 
+    pub async fn get_merged_archives(
+        &self,
+        room_id: u64,
+    ) -> Result<Vec<MergedSession>, RecorderManagerError> {
+        if let Some(recorder) = self.recorders.get(&room_id) {
+            Ok(recorder.get_merged_archives().await?)
+        } else {
+            Err(RecorderManagerError::NotFound { room_id })
+        }
+    }
+
     pub async fn get_archive(
         &self,
         room_id: u64,
@@ -225,6 +356,44 @@ impl RecorderManager {
         }
     }
 
+    pub async fn get_trickplay(
+        &self,
+        room_id: u64,
+        live_id: u64,
+    ) -> Result<Option<String>, RecorderManagerError> {
+        if let Some(recorder) = self.recorders.get(&room_id) {
+            Ok(recorder.get_trickplay(live_id).await)
+        } else {
+            Err(RecorderManagerError::NotFound { room_id })
+        }
+    }
+
+    pub async fn offset_to_time(
+        &self,
+        room_id: u64,
+        live_id: u64,
+        offset: f64,
+    ) -> Result<i64, RecorderManagerError> {
+        if let Some(recorder) = self.recorders.get(&room_id) {
+            Ok(recorder.offset_to_time(live_id, offset).await?)
+        } else {
+            Err(RecorderManagerError::NotFound { room_id })
+        }
+    }
+
+    pub async fn time_to_offset(
+        &self,
+        room_id: u64,
+        live_id: u64,
+        time: i64,
+    ) -> Result<f64, RecorderManagerError> {
+        if let Some(recorder) = self.recorders.get(&room_id) {
+            Ok(recorder.time_to_offset(live_id, time).await?)
+        } else {
+            Err(RecorderManagerError::NotFound { room_id })
+        }
+    }
+
     pub async fn delete_archive(&self, room_id: u64, ts: u64) {
         if let Some(recorder) = self.recorders.get(&room_id) {
             recorder.delete_archive(ts).await;
@@ -307,10 +476,15 @@ impl RecorderManager {
                                 );
                             }
                             let ts_file_content = ts_file_content.unwrap();
+                            let content_type = match path_segs[3].rsplit('.').next() {
+                                Some("vtt") => "text/vtt",
+                                Some("jpg") => "image/jpeg",
+                                _ => "video/MP2T",
+                            };
                             Ok::<_, Infallible>(
                                 Response::builder()
                                     .status(200)
-                                    .header("Content-Type", "video/MP2T")
+                                    .header("Content-Type", content_type)
                                     .header("Access-Control-Allow-Origin", "*")
                                     .header("Access-Control-Allow-Methods", "GET, OPTIONS")
                                     .body(Body::from(ts_file_content))
@@ -0,0 +1,745 @@
+use crate::db::{AccountRow, Database, DatabaseError, UploadTaskRow, VideoRow};
+use crate::recorder::bilibili::profile::{self, Profile};
+use crate::recorder::bilibili::upload::{UploadOptions, UploadProgress, UploadState};
+use crate::recorder::bilibili::errors::BiliClientError;
+use crate::recorder::bilibili::{ArchiveState, BiliClient};
+use crate::recorder::cover::{self, CoverAspect};
+use crate::recorder::format::ClipFormat;
+use crate::recorder::naming::NameContext;
+use crate::Config;
+use chrono::{DateTime, Local};
+use custom_error::custom_error;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+use tauri_plugin_notification::NotificationExt;
+use tokio::sync::{mpsc, Mutex, RwLock};
+use tokio::time::Instant;
+
+/// Seconds between checks of the queue
+const POLL_INTERVAL: u64 = 10;
+/// Delay before the first retry, doubled on every attempt
+const RETRY_DELAY_MS: i64 = 60 * 1000;
+const MAX_RETRY_DELAY_MS: i64 = 30 * 60 * 1000;
+/// Upload can not be held longer than publish window
+const MAX_HOLD_MS: i64 = 15 * 24 * 60 * 60 * 1000;
+/// Seconds between checks of review state of submitted videos
+const REVIEW_POLL_INTERVAL: u64 = 5 * 60;
+/// Parts allowed in one submission
+const MAX_PARTS: usize = 100;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadStatus {
+    Queued,
+    Uploading,
+    Submitting,
+    Done,
+    Failed,
+}
+
+impl UploadStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            UploadStatus::Queued => "queued",
+            UploadStatus::Uploading => "uploading",
+            UploadStatus::Submitting => "submitting",
+            UploadStatus::Done => "done",
+            UploadStatus::Failed => "failed",
+        }
+    }
+}
+
+/// One video of a multi-part submission
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UploadPart {
+    pub video_id: i64,
+    /// title of part, empty for `P{n}`
+    #[serde(default)]
+    pub title: String,
+}
+
+/// Upload the archive of a room automatically when its live ends
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AutoUploadRule {
+    pub enabled: bool,
+    /// archive is split into parts no longer than this in seconds, 0 to upload it as one video
+    #[serde(default)]
+    pub max_part_length: u64,
+    /// sessions shorter than this in seconds are skipped
+    #[serde(default)]
+    pub min_length: u64,
+    /// profile template, default template of room is used if not set
+    #[serde(default)]
+    pub template_id: Option<i64>,
+}
+
+impl AutoUploadRule {
+    /// Offset ranges of parts for archive of length seconds
+    pub fn split(&self, length: u64) -> Vec<(f64, f64)> {
+        if self.max_part_length == 0 || length <= self.max_part_length {
+            return vec![(0.0, length as f64)];
+        }
+        // parts of equal length instead of a short tail
+        let count = length.div_ceil(self.max_part_length);
+        let part = length as f64 / count as f64;
+        (0..count)
+            .map(|i| (i as f64 * part, ((i + 1) as f64 * part).min(length as f64)))
+            .collect()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_part_length != 0 && self.max_part_length < 60 {
+            return Err(format!(
+                "Max part length {}s is shorter than 60s",
+                self.max_part_length
+            ));
+        }
+        Ok(())
+    }
+}
+
+custom_error! {pub UploadManagerError
+    AlreadyQueued { video_id: i64 } = "Video {video_id} is already in upload queue",
+    InvalidStatus { id: i64, status: String } = "Upload task {id} is {status}",
+    InvalidFormat { format: String } = "Clip in {format} format can not be uploaded",
+    InvalidParts { count: usize } = "Submission with {count} parts is not allowed",
+    InvalidSchedule { reason: String } = "{reason}",
+    InvalidTemplate { err: String } = "Invalid profile template: {err}",
+    NoTemplate { room_id: u64 } = "No default profile template for room {room_id}",
+    InvalidCover { err: String } = "Invalid cover: {err}",
+    UploadFailed { err: String } = "{err}",
+    DatabaseError { err: DatabaseError } = "Database error: {err}",
+}
+
+impl From<String> for UploadManagerError {
+    fn from(value: String) -> Self {
+        UploadManagerError::UploadFailed { err: value }
+    }
+}
+
+impl From<DatabaseError> for UploadManagerError {
+    fn from(value: DatabaseError) -> Self {
+        UploadManagerError::DatabaseError { err: value }
+    }
+}
+
+impl From<UploadManagerError> for String {
+    fn from(value: UploadManagerError) -> Self {
+        value.to_string()
+    }
+}
+
+/// Queue of submissions persisted in database, tasks are retried with backoff until they
+/// reach max attempts of config, and tasks interrupted by exit are queued again on start.
+#[derive(Clone)]
+pub struct UploadManager {
+    app_handle: AppHandle,
+    db: Arc<Database>,
+    client: Arc<BiliClient>,
+    config: Arc<RwLock<Config>>,
+    /// video_id -> progress of running uploads
+    progress: Arc<RwLock<HashMap<i64, UploadProgress>>>,
+    /// ids of running tasks
+    running: Arc<Mutex<HashSet<i64>>>,
+}
+
+impl UploadManager {
+    pub fn new(
+        app_handle: AppHandle,
+        db: Arc<Database>,
+        client: Arc<BiliClient>,
+        config: Arc<RwLock<Config>>,
+    ) -> UploadManager {
+        UploadManager {
+            app_handle,
+            db,
+            client,
+            config,
+            progress: Arc::new(RwLock::new(HashMap::new())),
+            running: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    /// Queue tasks interrupted by last exit again, start polling the queue and review state of submitted videos
+    pub async fn run(&self) {
+        match self.db.get_upload_tasks().await {
+            Ok(tasks) => {
+                for mut task in tasks {
+                    if task.status == UploadStatus::Uploading.name()
+                        || task.status == UploadStatus::Submitting.name()
+                    {
+                        log::info!("Requeue interrupted upload task {}", task.id);
+                        task.status = UploadStatus::Queued.name().to_string();
+                        task.next_retry_at = 0;
+                        if let Err(e) = self.db.update_upload_task(&task).await {
+                            log::error!("Requeue upload task {} failed: {}", task.id, e);
+                        }
+                    }
+                }
+            }
+            Err(e) => log::error!("Load upload tasks failed: {}", e),
+        }
+        let manager = self.clone();
+        tokio::spawn(async move {
+            loop {
+                manager.schedule().await;
+                tokio::time::sleep(Duration::from_secs(POLL_INTERVAL)).await;
+            }
+        });
+        let manager = self.clone();
+        tokio::spawn(async move {
+            loop {
+                manager.check_reviews().await;
+                tokio::time::sleep(Duration::from_secs(REVIEW_POLL_INTERVAL)).await;
+            }
+        });
+    }
+
+    /// Add a submission into queue, parts are videos of the submission in order.
+    ///
+    /// Cover can be a data url, a local image, an http(s) url, or empty to use cover of the first
+    /// video, it is converted to 16:9 cover and 4:3 cover43 unless cover43 of profile is given.
+    /// cover43 is kept in its own column instead of the profile of task.
+    /// Task is held until start_at in milliseconds if it is set.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn enqueue(
+        &self,
+        uid: u64,
+        room_id: u64,
+        parts: Vec<UploadPart>,
+        cover: String,
+        profile: &Profile,
+        start_at: Option<i64>,
+    ) -> Result<UploadTaskRow, UploadManagerError> {
+        if parts.is_empty() || parts.len() > MAX_PARTS {
+            return Err(UploadManagerError::InvalidParts { count: parts.len() });
+        }
+        let now = chrono::Utc::now().timestamp_millis();
+        let start_at = start_at.unwrap_or(0);
+        if start_at > now + MAX_HOLD_MS {
+            return Err(UploadManagerError::InvalidSchedule {
+                reason: format!(
+                    "Upload can be held for at most {} days",
+                    MAX_HOLD_MS / 86400000
+                ),
+            });
+        }
+        // upload can not be submitted before it starts
+        profile
+            .validate_schedule(now.max(start_at) / 1000)
+            .map_err(|reason| UploadManagerError::InvalidSchedule { reason })?;
+        let mut videos = Vec::new();
+        for part in &parts {
+            if videos.iter().any(|v: &VideoRow| v.id == part.video_id) {
+                return Err(UploadManagerError::AlreadyQueued {
+                    video_id: part.video_id,
+                });
+            }
+            let video = self.db.get_video(part.video_id).await?;
+            if video.format != ClipFormat::Mp4.name() {
+                return Err(UploadManagerError::InvalidFormat {
+                    format: video.format,
+                });
+            }
+            videos.push(video);
+        }
+        for task in self.db.get_upload_tasks().await? {
+            if task.status == UploadStatus::Done.name()
+                || task.status == UploadStatus::Failed.name()
+            {
+                continue;
+            }
+            if let Some(part) = task_parts(&task)
+                .into_iter()
+                .find(|p| videos.iter().any(|v| v.id == p.video_id))
+            {
+                return Err(UploadManagerError::AlreadyQueued {
+                    video_id: part.video_id,
+                });
+            }
+        }
+        let source = if cover.is_empty() {
+            videos[0].cover.clone()
+        } else {
+            cover
+        };
+        let mut profile = profile.clone();
+        // 4:3 cover is cropped from cover if it is not given
+        let source43 = profile.cover43.take().unwrap_or(source.clone());
+        let (cover, cover43) = tokio::task::spawn_blocking(move || {
+            Ok::<_, String>((
+                cover::prepare(&source, CoverAspect::Wide)?,
+                cover::prepare(&source43, CoverAspect::Standard)?,
+            ))
+        })
+        .await
+        .map_err(|e| UploadManagerError::InvalidCover { err: e.to_string() })?
+        .map_err(|err| UploadManagerError::InvalidCover { err })?;
+        let task = self
+            .db
+            .add_upload_task(
+                parts[0].video_id,
+                uid,
+                room_id,
+                &cover,
+                &cover43,
+                &serde_json::to_string(&profile).unwrap(),
+                &serde_json::to_string(&parts).unwrap(),
+                UploadStatus::Queued.name(),
+                start_at,
+            )
+            .await?;
+        let _ = self.app_handle.emit("upload_task", task.clone());
+        self.schedule().await;
+        Ok(task)
+    }
+
+    pub async fn get_tasks(&self) -> Result<Vec<UploadTaskRow>, UploadManagerError> {
+        Ok(self.db.get_upload_tasks().await?)
+    }
+
+    /// Queue failed task again with attempts reset
+    pub async fn retry(&self, id: i64) -> Result<UploadTaskRow, UploadManagerError> {
+        let mut task = self.db.get_upload_task(id).await?;
+        if task.status != UploadStatus::Failed.name() {
+            return Err(UploadManagerError::InvalidStatus {
+                id,
+                status: task.status,
+            });
+        }
+        task.attempts = 0;
+        task.next_retry_at = 0;
+        self.update(&mut task, UploadStatus::Queued).await;
+        self.schedule().await;
+        Ok(task)
+    }
+
+    /// Remove task that is not running
+    pub async fn cancel(&self, id: i64) -> Result<(), UploadManagerError> {
+        let task = self.db.get_upload_task(id).await?;
+        if self.running.lock().await.contains(&id) {
+            return Err(UploadManagerError::InvalidStatus {
+                id,
+                status: task.status,
+            });
+        }
+        Ok(self.db.delete_upload_task(id).await?)
+    }
+
+    /// Render profile template for video, template_id defaults to the template of its room.
+    ///
+    /// Placeholders are the ones of name templates, taken from the archive the video was clipped from.
+    pub async fn render_profile(
+        &self,
+        video_id: i64,
+        template_id: Option<i64>,
+        streamer: &str,
+    ) -> Result<Profile, UploadManagerError> {
+        let video = self.db.get_video(video_id).await?;
+        let template_id = match template_id {
+            Some(id) => id,
+            None => *self
+                .config
+                .read()
+                .await
+                .room_profile_templates
+                .get(&video.room_id.to_string())
+                .ok_or(UploadManagerError::NoTemplate {
+                    room_id: video.room_id,
+                })?,
+        };
+        let template = self.db.get_profile_template(template_id).await?;
+        // source archive may be removed after clipping, or not recorded for older videos
+        let record = match video.live_id {
+            Some(live_id) => self.db.get_record(video.room_id, live_id).await.ok(),
+            None => None,
+        };
+        let date = record
+            .as_ref()
+            .and_then(|r| DateTime::parse_from_rfc3339(&r.created_at).ok())
+            .map(|d| d.with_timezone(&Local))
+            .unwrap_or(Local::now());
+        let ctx = NameContext {
+            room_id: video.room_id,
+            streamer: streamer.to_string(),
+            title: record.as_ref().map(|r| r.title.clone()).unwrap_or_default(),
+            live_id: record.as_ref().map(|r| r.live_id).unwrap_or(0),
+            offset: 0.0,
+            duration: video.length as f64,
+            date,
+        };
+        Profile::from_template(&template, &ctx)
+            .map_err(|err| UploadManagerError::InvalidTemplate { err })
+    }
+
+    pub async fn get_progress(&self, video_id: i64) -> Option<UploadProgress> {
+        self.progress.read().await.get(&video_id).cloned()
+    }
+
+    /// Start due tasks until concurrency of config is reached
+    async fn schedule(&self) {
+        let limit = self.config.read().await.upload_concurrency.max(1);
+        let now = chrono::Utc::now().timestamp_millis();
+        let tasks = match self
+            .db
+            .get_due_upload_tasks(UploadStatus::Queued.name(), now)
+            .await
+        {
+            Ok(tasks) => tasks,
+            Err(e) => {
+                log::error!("Load upload queue failed: {}", e);
+                return;
+            }
+        };
+        let mut running = self.running.lock().await;
+        for task in tasks {
+            if running.len() >= limit {
+                break;
+            }
+            if !running.insert(task.id) {
+                continue;
+            }
+            let manager = self.clone();
+            tokio::spawn(async move {
+                let id = task.id;
+                manager.process(id).await;
+                manager.running.lock().await.remove(&id);
+            });
+        }
+    }
+
+    async fn process(&self, id: i64) {
+        // task may be finished or removed since it was loaded by schedule
+        let mut task = match self.db.get_upload_task(id).await {
+            Ok(task) if task.status == UploadStatus::Queued.name() => task,
+            _ => return,
+        };
+        task.attempts += 1;
+        match self.upload(&mut task).await {
+            Ok(bvid) => {
+                task.bvid = bvid;
+                task.error = "".to_string();
+                self.update(&mut task, UploadStatus::Done).await;
+            }
+            Err(e) => {
+                log::error!(
+                    "Upload task {} failed at attempt {}: {}",
+                    task.id,
+                    task.attempts,
+                    e
+                );
+                // schedule does not become valid by retrying
+                let retryable = !matches!(e, UploadManagerError::InvalidSchedule { .. });
+                task.error = e.to_string();
+                let max_attempts = self.config.read().await.upload_max_attempts as i64;
+                if retryable && task.attempts < max_attempts {
+                    let delay =
+                        (RETRY_DELAY_MS << (task.attempts - 1).min(10)).min(MAX_RETRY_DELAY_MS);
+                    task.next_retry_at = chrono::Utc::now().timestamp_millis() + delay;
+                    self.update(&mut task, UploadStatus::Queued).await;
+                } else {
+                    self.update(&mut task, UploadStatus::Failed).await;
+                    let _ = self
+                        .db
+                        .new_message(
+                            "投稿失败",
+                            &format!("房间 {} 的切片投稿失败：{}", task.room_id, task.error),
+                        )
+                        .await;
+                }
+            }
+        }
+    }
+
+    /// Update review state of submitted videos that are not passed or rejected yet
+    async fn check_reviews(&self) {
+        let videos = match self.db.get_submitted_videos().await {
+            Ok(videos) => videos,
+            Err(e) => {
+                log::error!("Load submitted videos failed: {}", e);
+                return;
+            }
+        };
+        let tasks = self.db.get_upload_tasks().await.unwrap_or_default();
+        let primary_uid = self.config.read().await.primary_uid;
+        let mut checked = HashSet::new();
+        for video in videos {
+            if video.review_state.is_some_and(ArchiveState::is_final)
+                || !checked.insert(video.bvid.clone())
+            {
+                continue;
+            }
+            // only the account submitted the video can see its review state
+            let uid = tasks
+                .iter()
+                .find(|t| t.bvid == video.bvid)
+                .map(|t| t.uid)
+                .unwrap_or(primary_uid);
+            let account = match self.db.get_account(uid).await {
+                Ok(account) => account,
+                Err(e) => {
+                    log::warn!("Account {} of {} not found: {}", uid, video.bvid, e);
+                    continue;
+                }
+            };
+            let archive = match self.client.get_archive_state(&account, &video.bvid).await {
+                Ok(archive) => archive,
+                Err(e) => {
+                    log::error!("Get review state of {} failed: {}", video.bvid, e);
+                    continue;
+                }
+            };
+            if video.review_state == Some(archive.state) {
+                continue;
+            }
+            // videos submitted before review polling have no known state, they are seeded quietly
+            let notify = video.review_state.is_some();
+            log::info!(
+                "Review state of {} changed to {} {}",
+                video.bvid,
+                archive.state,
+                archive.state_desc
+            );
+            if let Err(e) = self
+                .db
+                .update_video_review(
+                    &video.bvid,
+                    archive.state,
+                    &archive.state_desc,
+                    &archive.reject_reason,
+                )
+                .await
+            {
+                log::error!("Update review state of {} failed: {}", video.bvid, e);
+                continue;
+            }
+            if !notify {
+                continue;
+            }
+            let (title, content) = if ArchiveState::is_passed(archive.state) {
+                ("审核通过", format!("投稿 {} 已通过审核", video.bvid))
+            } else if ArchiveState::is_rejected(archive.state) {
+                let reason = if archive.reject_reason.is_empty() {
+                    &archive.state_desc
+                } else {
+                    &archive.reject_reason
+                };
+                (
+                    "审核未通过",
+                    format!("投稿 {} 未通过审核：{}", video.bvid, reason),
+                )
+            } else {
+                continue;
+            };
+            let _ = self.db.new_message(title, &content).await;
+            if self.config.read().await.post_notify {
+                if let Err(e) = self
+                    .app_handle
+                    .notification()
+                    .builder()
+                    .title(format!("BiliShadowReplay - {}", title))
+                    .body(content)
+                    .show()
+                {
+                    log::warn!("Show notification failed: {}", e);
+                }
+            }
+        }
+    }
+
+    async fn update(&self, task: &mut UploadTaskRow, status: UploadStatus) {
+        task.status = status.name().to_string();
+        if let Err(e) = self.db.update_upload_task(task).await {
+            log::error!("Update upload task {} failed: {}", task.id, e);
+        }
+        let _ = self.app_handle.emit("upload_task", task.clone());
+    }
+
+    /// Upload videos of task and submit them, returns bvid
+    async fn upload(&self, task: &mut UploadTaskRow) -> Result<String, UploadManagerError> {
+        let mut profile: Profile =
+            serde_json::from_str(&task.profile).map_err(|e| e.to_string())?;
+        // held or retried tasks may have passed their schedule, parts are not uploaded for nothing
+        profile
+            .validate_schedule(chrono::Utc::now().timestamp())
+            .map_err(|reason| UploadManagerError::InvalidSchedule { reason })?;
+        self.update(task, UploadStatus::Uploading).await;
+        let account = self.db.get_account(task.uid).await?;
+        let parts = task_parts(task);
+        let cover = task.cover.clone();
+        let cover_url = self.upload_cover(&account, &cover);
+        let mut videos = Vec::new();
+        for (index, part) in parts.iter().enumerate() {
+            let mut video = self
+                .upload_part(&account, part.video_id)
+                .await
+                .map_err(|e| format!("Upload video {} failed: {}", part.video_id, e))?;
+            // single video takes title of submission
+            if parts.len() > 1 {
+                video.title = if part.title.is_empty() {
+                    format!("P{}", index + 1)
+                } else {
+                    part.title.clone()
+                };
+            }
+            videos.push(video);
+        }
+        self.update(task, UploadStatus::Submitting).await;
+        // uploading may take long enough to push schedule out of range
+        profile
+            .validate_schedule(chrono::Utc::now().timestamp())
+            .map_err(|reason| UploadManagerError::InvalidSchedule { reason })?;
+        profile.cover = cover_url.await.unwrap_or("".to_string());
+        // tasks queued before cover43 column keep it in profile
+        if let Some(cover43) = task.cover43.clone().or(profile.cover43.take()) {
+            match self.upload_cover(&account, &cover43).await {
+                Ok(url) => profile.cover43 = Some(url),
+                Err(e) => log::warn!("Upload cover43 failed: {}", e),
+            }
+        }
+        let ret = self
+            .client
+            .submit_video(&account, &profile, &videos)
+            .await
+            .map_err(|e| format!("Submit video failed: {}", e))?;
+        // uploaded files are kept by server until submission, states are useless after that
+        for part in &parts {
+            if let Err(e) = self.db.remove_upload(part.video_id).await {
+                log::warn!("Remove upload state of {} failed: {}", part.video_id, e);
+            }
+        }
+        // update video status and details of every part
+        // 1 means uploaded
+        for part in &parts {
+            self.db
+                .update_video(
+                    part.video_id,
+                    1,
+                    &ret.bvid,
+                    &profile.title,
+                    &profile.desc,
+                    &profile.tag,
+                    profile.tid,
+                )
+                .await?;
+        }
+        // known state lets review polling notify when it is passed or rejected
+        if let Err(e) = self
+            .db
+            .update_video_review(&ret.bvid, ArchiveState::REVIEWING, "待审核", "")
+            .await
+        {
+            log::warn!("Update review state of {} failed: {}", ret.bvid, e);
+        }
+        let content = if parts.len() > 1 {
+            format!(
+                "投稿了房间 {} 的 {} 个切片：{}",
+                task.room_id,
+                parts.len(),
+                ret.bvid
+            )
+        } else {
+            format!("投稿了房间 {} 的切片：{}", task.room_id, ret.bvid)
+        };
+        self.db.new_message("投稿成功", &content).await?;
+        if self.config.read().await.post_notify {
+            if let Err(e) = self
+                .app_handle
+                .notification()
+                .builder()
+                .title("BiliShadowReplay - 投稿成功")
+                .body(content)
+                .show()
+            {
+                log::warn!("Show notification failed: {}", e);
+            }
+        }
+        Ok(ret.bvid)
+    }
+
+    /// Upload data url cover, remote cover is used as is
+    async fn upload_cover(
+        &self,
+        account: &AccountRow,
+        cover: &str,
+    ) -> Result<String, BiliClientError> {
+        if cover::is_remote(cover) {
+            return Ok(cover.to_string());
+        }
+        self.client.upload_cover(account, cover).await
+    }
+
+    /// Upload file of video, upload state is persisted after every chunk so it can resume after restart
+    async fn upload_part(
+        &self,
+        account: &AccountRow,
+        video_id: i64,
+    ) -> Result<profile::Video, String> {
+        let video = self.db.get_video(video_id).await?;
+        let (file, options) = {
+            let config = self.config.read().await;
+            (
+                format!("{}/{}", config.output, video.file),
+                UploadOptions {
+                    workers: config.upload_workers,
+                    retries: config.upload_retries,
+                },
+            )
+        };
+        let path = Path::new(&file);
+        let saved = self
+            .db
+            .get_upload(video_id)
+            .await
+            .ok()
+            .and_then(|row| serde_json::from_str::<UploadState>(&row.state).ok());
+        let (progress, mut states) = mpsc::unbounded_channel::<UploadState>();
+        let db = self.db.clone();
+        let uploads = self.progress.clone();
+        let app_handle = self.app_handle.clone();
+        let persist = tokio::spawn(async move {
+            // first state is sent before any chunk of this run is uploaded
+            let mut start = None;
+            while let Some(upload) = states.recv().await {
+                if let Ok(content) = serde_json::to_string(&upload) {
+                    if let Err(e) = db.save_upload(video_id, &content).await {
+                        log::error!("Save upload state failed: {}", e);
+                    }
+                }
+                let (started, resumed) =
+                    *start.get_or_insert((Instant::now(), upload.uploaded_bytes()));
+                let progress = UploadProgress::new(
+                    video_id,
+                    &upload,
+                    resumed,
+                    started.elapsed().as_secs_f64(),
+                );
+                let _ = app_handle.emit(&format!("upload:{}", video_id), progress.clone());
+                uploads.write().await.insert(video_id, progress);
+            }
+        });
+        let prepared = self
+            .client
+            .prepare_video(account, path, saved, &options, progress)
+            .await;
+        let _ = persist.await;
+        self.progress.write().await.remove(&video_id);
+        // state is kept until submission so failed submissions do not upload the file again
+        Ok(prepared?)
+    }
+}
+
+/// Parts of task, tasks added before multi-part submissions only have video_id
+fn task_parts(task: &UploadTaskRow) -> Vec<UploadPart> {
+    serde_json::from_str::<Vec<UploadPart>>(&task.parts)
+        .ok()
+        .filter(|parts| !parts.is_empty())
+        .unwrap_or(vec![UploadPart {
+            video_id: task.video_id,
+            title: "".to_string(),
+        }])
+}
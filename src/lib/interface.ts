@@ -43,6 +43,7 @@ export interface Video {
 export interface VideoItem {
   id: number;
   room_id: number;
+  // data url, or path of cover file set from local image
   cover: string;
   file: string;
   length: number;
@@ -54,6 +55,15 @@ export interface VideoItem {
   tags: string;
   area: number;
   created_at: string;
+  format: ClipFormat;
+  // JSON of LoudnessReport
+  loudness: string | null;
+  // state of submitted archive, 0 is open
+  review_state: number | null;
+  review_desc: string | null;
+  reject_reason: string | null;
+  // archive the clip starts in, null for videos added before it is recorded
+  live_id: number | null;
 }
 
 export interface Profile {
@@ -79,6 +89,8 @@ export interface Profile {
   up_close_reply: boolean;
   up_close_danmu: boolean;
   web_os: 0 | 1;
+  // scheduled publish time in seconds
+  dtime?: number;
 }
 
 export interface Config {
@@ -89,6 +101,19 @@ export interface Config {
   live_end_notify: boolean;
   clip_notify: boolean;
   post_notify: boolean;
+  encoding_presets: EncodingPreset[];
+  room_presets: Record<string, string>;
+  room_profile_templates: Record<string, number>;
+  auto_upload_rules: Record<string, AutoUploadRule>;
+  clip_name_template: string;
+  archive_name_template: string;
+  clip_sidecar: boolean;
+  // font file of compilation title cards, platform font if empty
+  title_font: string;
+  upload_workers: number;
+  upload_retries: number;
+  upload_concurrency: number;
+  upload_max_attempts: number;
 }
 
 export interface DiskInfo {
@@ -96,3 +121,178 @@ export interface DiskInfo {
   total: number;
   free: number;
 }
+
+export interface BookmarkItem {
+  id: number;
+  room_id: number;
+  live_id: number;
+  offset: number;
+  note: string;
+  created_at: string;
+}
+
+export interface SessionSpan {
+  live_id: number;
+  title: string;
+  start: number;
+  end: number;
+  length: number;
+}
+
+export interface MergedSession {
+  start: number;
+  end: number;
+  length: number;
+  sessions: SessionSpan[];
+}
+
+export interface ClipRange {
+  live_id: number;
+  start: number;
+  end: number;
+  title?: string;
+}
+
+export interface CompilationOptions {
+  transition?: number;
+  title_card?: number;
+}
+
+export interface EncodingPreset {
+  name: string;
+  video_codec: string;
+  crf: number | null;
+  speed: string | null;
+  width: number | null;
+  height: number | null;
+  fps: number | null;
+  audio_codec: string;
+  audio_bitrate: number | null;
+}
+
+export interface ClipOptions {
+  preset?: string;
+  layout?: number;
+  name_template?: string;
+  format?: ClipFormat;
+  animation?: AnimationOptions;
+  loudnorm?: LoudnormOptions;
+}
+
+export type ClipFormat = "mp4" | "audio" | "gif" | "webp";
+
+export interface LoudnormOptions {
+  target?: number;
+  true_peak?: number;
+  lra?: number;
+}
+
+export interface LoudnessReport {
+  target: number;
+  input_i: number | null;
+  input_tp: number | null;
+  input_lra: number | null;
+  output_i: number | null;
+  output_tp: number | null;
+  output_lra: number | null;
+}
+
+export interface AnimationOptions {
+  fps: number;
+  width: number;
+}
+
+export interface CropRegion {
+  x: number;
+  y: number;
+  w: number;
+  h: number;
+}
+
+export interface LayoutItem {
+  id: number;
+  room_id: number;
+  name: string;
+  width: number;
+  height: number;
+  blur_background: boolean;
+  regions: string;
+  created_at: string;
+}
+
+export interface ClipSource {
+  live_id: number;
+  start: number;
+  end: number;
+}
+
+export interface ClipMetadata {
+  title: string;
+  streamer: string;
+  room_id: number;
+  sources: ClipSource[];
+  duration: number;
+  recorded_at: string;
+  created_at: string;
+  tool: string;
+  loudness?: LoudnessReport;
+}
+
+export interface UploadProgress {
+  video_id: number;
+  uploaded: number;
+  total: number;
+  chunk: number;
+  uploaded_chunks: number;
+  total_chunks: number;
+  speed: number;
+  eta: number;
+}
+
+export type UploadStatus =
+  | "queued"
+  | "uploading"
+  | "submitting"
+  | "done"
+  | "failed";
+
+export interface UploadTask {
+  id: number;
+  video_id: number;
+  uid: number;
+  room_id: number;
+  cover: string;
+  profile: string;
+  status: UploadStatus;
+  attempts: number;
+  error: string;
+  bvid: string;
+  next_retry_at: number;
+  created_at: string;
+  updated_at: string;
+  parts: string;
+}
+
+export interface UploadPart {
+  video_id: number;
+  title: string;
+}
+
+export interface ProfileTemplate {
+  id: number;
+  name: string;
+  title: string;
+  tid: number;
+  tag: string;
+  desc: string;
+  copyright: 1 | 2;
+  dynamic: string;
+  created_at: string;
+}
+
+export interface AutoUploadRule {
+  enabled: boolean;
+  max_part_length: number;
+  min_length: number;
+  template_id: number | null;
+}